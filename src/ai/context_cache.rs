@@ -0,0 +1,149 @@
+//! Explicit Gemini context caching. The system instruction is uploaded once
+//! as a `cachedContents` resource and later requests refer to it by name; the
+//! names are remembered on disk until the resource expires. Instructions too
+//! small for Gemini to cache are remembered too, so they are counted once.
+
+use super::{Content, Part, count_gemini_tokens, send_request};
+use crate::cache;
+use crate::config::{CONTEXT_CACHE_EXPIRY_MARGIN_SECS, ContextCacheConfig};
+use crate::preflight;
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The smallest content Gemini caches explicitly; the API rejects anything
+/// smaller, and some models need more.
+const MIN_CACHED_TOKENS: u64 = 1024;
+
+#[derive(Serialize)]
+struct CreateRequest<'a> {
+    model: String,
+    #[serde(rename = "systemInstruction")]
+    system_instruction: Content,
+    ttl: String,
+    #[serde(rename = "displayName")]
+    display_name: &'a str,
+}
+
+#[derive(Deserialize)]
+struct CreateResponse {
+    name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// The resource name, `cachedContents/…`; `None` when the instructions
+    /// are too small to cache.
+    name: Option<String>,
+    expires_at: u64,
+}
+
+pub(super) struct ContextCache {
+    dir: PathBuf,
+    ttl_secs: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl ContextCache {
+    /// `None` when context caching is disabled or no cache directory is
+    /// available.
+    pub(super) fn from_config(config: &ContextCacheConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let dir = match &config.dir {
+            Some(dir) => dir.clone(),
+            None => dirs::cache_dir()?.join("ai-commit").join("contexts"),
+        };
+        Some(ContextCache {
+            dir,
+            ttl_secs: config.ttl_secs,
+        })
+    }
+
+    /// Returns the name of a cached content holding `system` for `model_id`,
+    /// creating one when none is known or the known one is about to expire.
+    /// `None` when `system` is under Gemini's minimum size for caching, as
+    /// the default instructions alone are.
+    pub(super) async fn get_or_create(
+        &self,
+        client: &Client,
+        base_url: &str,
+        api_key: &str,
+        model_id: &str,
+        system: &str,
+    ) -> Result<Option<String>> {
+        if preflight::estimate_locally(system) < MIN_CACHED_TOKENS {
+            return Ok(None);
+        }
+        // The key is part of it because caches belong to a project.
+        let key = cache::cache_key(&[base_url, api_key, model_id, system]);
+        let path = self.dir.join(format!("{}.json", key));
+        if let Some(entry) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Entry>(&contents).ok())
+            && entry.expires_at > now_secs() + CONTEXT_CACHE_EXPIRY_MARGIN_SECS
+        {
+            return Ok(entry.name);
+        }
+
+        // The local estimate is rough; Gemini's own count decides.
+        let tokens = count_gemini_tokens(client, base_url, api_key, model_id, system).await?;
+        if tokens < MIN_CACHED_TOKENS {
+            self.remember(
+                &path,
+                &Entry {
+                    name: None,
+                    expires_at: now_secs() + self.ttl_secs,
+                },
+            );
+            return Ok(None);
+        }
+
+        let url = format!("{}/cachedContents", base_url.trim_end_matches('/'));
+        let payload = CreateRequest {
+            model: format!("models/{}", model_id),
+            system_instruction: Content {
+                parts: vec![Part {
+                    text: system.to_string(),
+                }],
+            },
+            ttl: format!("{}s", self.ttl_secs),
+            display_name: "ai-commit prompt instructions",
+        };
+        let request = client
+            .post(&url)
+            .header("x-goog-api-key", api_key)
+            .json(&payload);
+        let body = send_request(request, "Gemini").await?;
+        let created: CreateResponse = serde_json::from_str(&body)
+            .context("Failed to parse Gemini cachedContents response")?;
+
+        self.remember(
+            &path,
+            &Entry {
+                name: Some(created.name.clone()),
+                expires_at: now_secs() + self.ttl_secs,
+            },
+        );
+        Ok(Some(created.name))
+    }
+
+    /// Remembering an entry only saves a later request; failing to is not an error.
+    fn remember(&self, path: &Path, entry: &Entry) {
+        if fs::create_dir_all(&self.dir).is_ok()
+            && let Ok(json) = serde_json::to_string(entry)
+        {
+            let _ = fs::write(path, json);
+        }
+    }
+}
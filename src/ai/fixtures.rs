@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use reqwest::header::{CONTENT_TYPE, HeaderMap, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// When set to a directory, every API response is also written there as a
+/// fixture file that the test mock server can replay.
+pub const RECORD_FIXTURES_ENV_VAR: &str = "AI_COMMIT_RECORD_FIXTURES";
+
+/// A captured API response. Only the parts the client looks at are kept, so
+/// fixtures never contain request URLs or credentials.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    pub api: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<String>,
+    /// Set for streamed responses, whose body is the raw event stream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+impl Fixture {
+    pub fn new(api: &str, status: StatusCode, headers: &HeaderMap, body: &str) -> Self {
+        Fixture {
+            api: api.to_string(),
+            status: status.as_u16(),
+            retry_after: headers
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            content_type: headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| value.starts_with("text/event-stream"))
+                .map(str::to_string),
+            body: body.to_string(),
+        }
+    }
+}
+
+/// Writes `fixture` to the next free `NNNN-<api>.json` file in `dir`, so a
+/// recorded session replays in the order it happened.
+pub fn write_fixture(dir: &Path, fixture: &Fixture) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create fixture directory {:?}", dir))?;
+    let index = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read fixture directory {:?}", dir))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+        .count();
+    let path = dir.join(format!(
+        "{:04}-{}.json",
+        index + 1,
+        fixture.api.to_lowercase()
+    ));
+    let contents = serde_json::to_string_pretty(fixture).context("Failed to serialize fixture")?;
+    fs::write(&path, contents).with_context(|| format!("Failed to write fixture {:?}", path))
+}
+
+/// Records the response if fixture recording is enabled. Recording is a
+/// development aid, so failures are reported but never fail the request.
+pub fn record(api: &str, status: StatusCode, headers: &HeaderMap, body: &str) {
+    let Ok(dir) = env::var(RECORD_FIXTURES_ENV_VAR) else {
+        return;
+    };
+    let fixture = Fixture::new(api, status, headers, body);
+    if let Err(e) = write_fixture(Path::new(&dir), &fixture) {
+        eprintln!("⚠️ Failed to record fixture: {:#}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_fixtures_are_numbered_in_order() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+        let limited = Fixture::new(
+            "Gemini",
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers,
+            "{\"error\": {}}",
+        );
+        let ok = Fixture::new("OpenAI", StatusCode::OK, &HeaderMap::new(), "{}");
+        write_fixture(temp_dir.path(), &limited)?;
+        write_fixture(temp_dir.path(), &ok)?;
+
+        let first: Fixture = serde_json::from_str(&fs::read_to_string(
+            temp_dir.path().join("0001-gemini.json"),
+        )?)?;
+        assert_eq!(first, limited);
+        assert_eq!(first.retry_after.as_deref(), Some("3"));
+        let second: Fixture = serde_json::from_str(&fs::read_to_string(
+            temp_dir.path().join("0002-openai.json"),
+        )?)?;
+        assert_eq!(second, ok);
+        temp_dir.close()?;
+        Ok(())
+    }
+}
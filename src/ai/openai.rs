@@ -0,0 +1,318 @@
+use super::safety::Stop;
+use super::stream::Delta;
+use super::{
+    CandidateRequest, Completion, Usage, drop_unterminated_line, send_request,
+    send_streaming_request,
+};
+use crate::prompt::Prompt;
+use crate::redact;
+use anyhow::{Context, Result, bail};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const OPENAI_API_BASE_URL: &str = "https://api.openai.com/v1";
+
+#[derive(Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage<'a>>,
+    n: u32,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_completion_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    stop: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Serialize)]
+struct StreamOptions {
+    /// Asks for a final chunk carrying the token usage.
+    include_usage: bool,
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatCompletionResponse {
+    choices: Option<Vec<Choice>>,
+    error: Option<ErrorDetail>,
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+struct TokenUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+impl From<TokenUsage> for Usage {
+    fn from(usage: TokenUsage) -> Self {
+        Usage {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Choice {
+    #[serde(default)]
+    index: u32,
+    message: Option<ResponseMessage>,
+    /// The increment carried by a streamed chunk, in place of `message`.
+    delta: Option<ResponseMessage>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResponseMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorDetail {
+    message: String,
+}
+
+fn parse_response(body: &str) -> Result<ChatCompletionResponse> {
+    let response_body: ChatCompletionResponse =
+        serde_json::from_str(body).context("Failed to parse OpenAI API response")?;
+    if let Some(error) = response_body.error {
+        bail!(
+            "OpenAI API returned an error: {}",
+            redact::redact(&error.message)
+        );
+    }
+    Ok(response_body)
+}
+
+/// The fixed instructions go in a system message, which also lets the
+/// provider reuse its cache of the prompt prefix.
+fn chat_messages(prompt: &Prompt) -> Vec<ChatMessage<'_>> {
+    let mut messages = Vec::new();
+    if !prompt.system.is_empty() {
+        messages.push(ChatMessage {
+            role: "system",
+            content: &prompt.system,
+        });
+    }
+    messages.push(ChatMessage {
+        role: "user",
+        content: &prompt.request,
+    });
+    messages
+}
+
+/// Chat completions report hitting the token limit as `length`.
+fn stop(finish_reason: Option<&str>) -> Option<Stop> {
+    (finish_reason == Some("length")).then_some(Stop::MaxTokens)
+}
+
+fn completion(response_body: ChatCompletionResponse) -> Completion {
+    Completion {
+        usage: response_body.usage.map(Usage::from).unwrap_or_default(),
+        stop: response_body
+            .choices
+            .iter()
+            .flatten()
+            .find_map(|choice| stop(choice.finish_reason.as_deref())),
+        texts: response_body
+            .choices
+            .into_iter()
+            .flatten()
+            .filter_map(|choice| {
+                let mut text = choice.message?.content?;
+                if stop(choice.finish_reason.as_deref()).is_some() {
+                    drop_unterminated_line(&mut text);
+                }
+                Some(text)
+            })
+            .collect(),
+    }
+}
+
+/// Requests chat completions. With `on_delta` the response is streamed and
+/// every piece of choice text is passed on as it arrives. Chat completions
+/// have no equivalent of `top_k` or a thinking budget, so those are not sent.
+pub async fn request_candidates(
+    client: &Client,
+    base_url: &str,
+    api_key: &str,
+    model_id: &str,
+    request: &CandidateRequest<'_>,
+    on_delta: Option<&(dyn Fn(Delta) + Sync)>,
+) -> Result<Completion> {
+    let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+    let params = request.params;
+    let request_payload = ChatCompletionRequest {
+        model: model_id,
+        messages: chat_messages(request.prompt),
+        n: request.num_candidates.max(1),
+        stream: on_delta.is_some(),
+        temperature: params.temperature,
+        top_p: params.top_p,
+        max_completion_tokens: params.max_output_tokens,
+        stop: &params.stop,
+        seed: params.seed,
+        stream_options: on_delta.map(|_| StreamOptions {
+            include_usage: true,
+        }),
+    };
+    let request = client
+        .post(&url)
+        .bearer_auth(api_key)
+        .json(&request_payload);
+
+    let Some(on_delta) = on_delta else {
+        let body = send_request(request, "OpenAI").await?;
+        return Ok(completion(parse_response(&body)?));
+    };
+    let mut texts: BTreeMap<u32, String> = BTreeMap::new();
+    let mut usage = Usage::default();
+    let mut length_stop = None;
+    let mut on_event = |data: &str| -> Result<()> {
+        if data == "[DONE]" {
+            return Ok(());
+        }
+        let chunk = parse_response(data)?;
+        if let Some(chunk_usage) = chunk.usage {
+            usage = chunk_usage.into();
+            on_delta(Delta::Usage(usage));
+        }
+        for choice in chunk.choices.into_iter().flatten() {
+            if let Some(text) = choice.delta.and_then(|delta| delta.content) {
+                texts.entry(choice.index).or_default().push_str(&text);
+                on_delta(Delta::Text(choice.index, &text));
+            }
+            if choice.finish_reason.is_some() {
+                match stop(choice.finish_reason.as_deref()) {
+                    Some(cut_off) => {
+                        if let Some(text) = texts.get_mut(&choice.index) {
+                            drop_unterminated_line(text);
+                        }
+                        on_delta(Delta::CutOff(choice.index));
+                        length_stop.get_or_insert(cut_off);
+                    }
+                    None => on_delta(Delta::Finished(choice.index)),
+                }
+            }
+        }
+        Ok(())
+    };
+    match send_streaming_request(request, "OpenAI", &mut on_event).await? {
+        Some(body) => Ok(completion(parse_response(&body)?)),
+        None => Ok(Completion {
+            texts: texts.into_values().collect(),
+            usage,
+            stop: length_stop,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_serialization() {
+        let request = ChatCompletionRequest {
+            model: "gpt-4o-mini",
+            messages: vec![ChatMessage {
+                role: "user",
+                content: "prompt",
+            }],
+            n: 3,
+            stream: false,
+            temperature: None,
+            top_p: None,
+            max_completion_tokens: None,
+            stop: &[],
+            seed: None,
+            stream_options: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "model": "gpt-4o-mini",
+                "messages": [{"role": "user", "content": "prompt"}],
+                "n": 3
+            })
+        );
+
+        let stop = vec!["\n\n".to_string()];
+        let request = ChatCompletionRequest {
+            model: "gpt-4o-mini",
+            messages: Vec::new(),
+            n: 1,
+            stream: true,
+            temperature: Some(0.5),
+            top_p: Some(0.25),
+            max_completion_tokens: Some(200),
+            stop: &stop,
+            seed: Some(7),
+            stream_options: Some(StreamOptions {
+                include_usage: true,
+            }),
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "model": "gpt-4o-mini",
+                "messages": [],
+                "n": 1,
+                "stream": true,
+                "temperature": 0.5,
+                "top_p": 0.25,
+                "max_completion_tokens": 200,
+                "stop": ["\n\n"],
+                "seed": 7,
+                "stream_options": {"include_usage": true}
+            })
+        );
+    }
+
+    #[test]
+    fn test_response_deserialization() {
+        let body = r#"{"choices": [
+            {"index": 0, "message": {"role": "assistant", "content": "feat: Add caching layer"}},
+            {"index": 1, "message": {"role": "assistant", "content": null}}
+        ]}"#;
+        let response: ChatCompletionResponse = serde_json::from_str(body).unwrap();
+        let choices = response.choices.unwrap();
+        assert_eq!(choices.len(), 2);
+        assert_eq!(
+            choices[0].message.as_ref().unwrap().content.as_deref(),
+            Some("feat: Add caching layer")
+        );
+        assert!(choices[1].message.as_ref().unwrap().content.is_none());
+    }
+
+    #[test]
+    fn test_usage_is_parsed() {
+        let body = r#"{"choices": [{"index": 0, "message": {"content": "fix: Typo"}}],
+            "usage": {"prompt_tokens": 812, "completion_tokens": 9, "total_tokens": 821}}"#;
+        let completion = completion(parse_response(body).unwrap());
+        assert_eq!(completion.texts, vec!["fix: Typo"]);
+        assert_eq!(
+            completion.usage,
+            Usage {
+                input_tokens: 812,
+                output_tokens: 9
+            }
+        );
+    }
+}
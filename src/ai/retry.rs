@@ -0,0 +1,367 @@
+use crate::config::RetryConfig;
+use crate::redact;
+use anyhow::Result;
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// A failed API call, classified so the retry loop knows whether another
+/// attempt can help.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Option<StatusCode>,
+    pub retryable: bool,
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ApiError {
+    pub fn from_status(
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &str,
+        message: String,
+    ) -> Self {
+        let retry_after = parse_retry_after_header(headers).or_else(|| parse_retry_info(body));
+        ApiError {
+            status: Some(status),
+            retryable: is_retryable_status(status),
+            retry_after,
+            message: redact::redact(&message),
+        }
+    }
+
+    /// Timeouts, failed connections and bodies cut off while being read are
+    /// retried. Other errors, such as a malformed response or a refused
+    /// redirect, would fail the same way again.
+    pub fn from_transport(err: &reqwest::Error, message: String) -> Self {
+        // A body read error surfaces as a decode error when it passes
+        // through reqwest's decoder.
+        let body_read = err.is_body() || err.is_decode();
+        ApiError {
+            status: err.status(),
+            retryable: err.is_timeout() || err.is_connect() || body_read,
+            retry_after: None,
+            message: redact::redact(&format!("{}: {}", message, err)),
+        }
+    }
+
+    fn short_reason(&self) -> String {
+        match self.status {
+            Some(status) => status.to_string(),
+            None => "network error".to_string(),
+        }
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+fn parse_retry_after_header(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// Reads the delay from a `google.rpc.RetryInfo` entry in a Gemini error body,
+/// e.g. `{"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "13s"}`.
+fn parse_retry_info(body: &str) -> Option<Duration> {
+    let json: Value = serde_json::from_str(body).ok()?;
+    let details = json.get("error")?.get("details")?.as_array()?;
+    details
+        .iter()
+        .filter(|detail| {
+            detail
+                .get("@type")
+                .and_then(Value::as_str)
+                .is_some_and(|t| t.ends_with("google.rpc.RetryInfo"))
+        })
+        .find_map(|detail| detail.get("retryDelay")?.as_str())
+        .and_then(|delay| delay.strip_suffix('s')?.parse::<f64>().ok())
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub deadline: Duration,
+}
+
+impl From<&RetryConfig> for RetryPolicy {
+    fn from(config: &RetryConfig) -> Self {
+        RetryPolicy {
+            max_attempts: config.max_attempts.max(1),
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+            deadline: Duration::from_secs(config.deadline_secs),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff for the given (1-based) failed attempt with jitter
+    /// drawn from the upper half of the window.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let window = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        window.mul_f64(0.5 + fastrand::f64() * 0.5)
+    }
+}
+
+pub struct RetryNotice<'a> {
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub delay: Duration,
+    pub error: &'a ApiError,
+}
+
+impl fmt::Display for RetryNotice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "⏳ {}, retrying in {:.1}s (attempt {}/{})",
+            self.error.short_reason(),
+            self.delay.as_secs_f64(),
+            self.attempt + 1,
+            self.max_attempts
+        )
+    }
+}
+
+/// Runs `operation` until it succeeds, fails with a non-retryable error, or
+/// the policy's attempts or deadline are exhausted.
+pub async fn with_retries<T, F, Fut>(
+    policy: &RetryPolicy,
+    on_retry: &(dyn Fn(&RetryNotice) + Sync),
+    mut operation: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let started = Instant::now();
+    let mut attempt = 1;
+    loop {
+        let err = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        let Some(api_error) = err.downcast_ref::<ApiError>() else {
+            return Err(err);
+        };
+        if !api_error.retryable || attempt >= policy.max_attempts {
+            return Err(err);
+        }
+        let delay = api_error
+            .retry_after
+            .unwrap_or_else(|| policy.backoff(attempt));
+        if started.elapsed() + delay > policy.deadline {
+            return Err(err.context(format!(
+                "Giving up after {} attempt(s): retry deadline of {}s reached",
+                attempt,
+                policy.deadline.as_secs()
+            )));
+        }
+        on_retry(&RetryNotice {
+            attempt,
+            max_attempts: policy.max_attempts,
+            delay,
+            error: api_error,
+        });
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use reqwest::header::HeaderValue;
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+            deadline: Duration::from_secs(5),
+        }
+    }
+
+    fn api_error(status: StatusCode) -> anyhow::Error {
+        ApiError::from_status(status, &HeaderMap::new(), "", status.to_string()).into()
+    }
+
+    #[test]
+    fn test_status_classification() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable_status(StatusCode::UNAUTHORIZED));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_transport_classification() -> Result<()> {
+        // Nothing listens on the port once the listener is dropped.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let refused = reqwest::get(format!("http://127.0.0.1:{}", port))
+            .await
+            .unwrap_err();
+        assert!(ApiError::from_transport(&refused, String::new()).retryable);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            if let Ok((mut stream, _)) = listener.accept() {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = stream.write_all(b"not an HTTP response\r\n\r\n");
+            }
+        });
+        let malformed = reqwest::get(url).await.unwrap_err();
+        assert!(!ApiError::from_transport(&malformed, String::new()).retryable);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_after_header_and_retry_info() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(
+            parse_retry_after_header(&headers),
+            Some(Duration::from_secs(7))
+        );
+
+        let body = r#"{"error": {"code": 429, "status": "RESOURCE_EXHAUSTED", "details": [
+            {"@type": "type.googleapis.com/google.rpc.QuotaFailure"},
+            {"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "1.5s"}
+        ]}}"#;
+        assert_eq!(parse_retry_info(body), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_retry_info("not json"), None);
+
+        let error =
+            ApiError::from_status(StatusCode::TOO_MANY_REQUESTS, &headers, body, "x".into());
+        assert_eq!(error.retry_after, Some(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(400),
+            deadline: Duration::from_secs(60),
+        };
+        for _ in 0..20 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let capped = policy.backoff(8);
+            assert!(capped >= Duration::from_millis(200) && capped <= Duration::from_millis(400));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success() -> Result<()> {
+        let calls = Cell::new(0);
+        let notices = AtomicU32::new(0);
+        let value = with_retries(
+            &fast_policy(4),
+            &|_| {
+                notices.fetch_add(1, Ordering::Relaxed);
+            },
+            || {
+                calls.set(calls.get() + 1);
+                let attempt = calls.get();
+                async move {
+                    if attempt < 3 {
+                        Err(api_error(StatusCode::SERVICE_UNAVAILABLE))
+                    } else {
+                        Ok("done")
+                    }
+                }
+            },
+        )
+        .await?;
+        assert_eq!(value, "done");
+        assert_eq!(calls.get(), 3);
+        assert_eq!(notices.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fatal_and_unclassified_errors_are_not_retried() {
+        let calls = Cell::new(0);
+        let result: Result<()> = with_retries(&fast_policy(4), &|_| {}, || {
+            calls.set(calls.get() + 1);
+            async { Err(api_error(StatusCode::BAD_REQUEST)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+
+        calls.set(0);
+        let result: Result<()> = with_retries(&fast_policy(4), &|_| {}, || {
+            calls.set(calls.get() + 1);
+            async { Err(anyhow!("parse failure")) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts_and_deadline() {
+        let calls = Cell::new(0);
+        let result: Result<()> = with_retries(&fast_policy(3), &|_| {}, || {
+            calls.set(calls.get() + 1);
+            async { Err(api_error(StatusCode::TOO_MANY_REQUESTS)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 3);
+
+        let mut policy = fast_policy(5);
+        policy.deadline = Duration::from_millis(0);
+        let result: Result<()> = with_retries(&policy, &|_| {}, || async {
+            Err(api_error(StatusCode::TOO_MANY_REQUESTS))
+        })
+        .await;
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("retry deadline of 0s reached")
+        );
+    }
+}
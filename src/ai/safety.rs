@@ -0,0 +1,261 @@
+//! Gemini safety settings, and the reasons a response can end without usable
+//! text: a blocked prompt (`promptFeedback.blockReason`) or a candidate's
+//! `finishReason` with its `safetyRatings`.
+
+use crate::config::{GenerationConfig, SafetyThreshold};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The categories `generation.safety_threshold` applies to.
+const HARM_CATEGORIES: [&str; 4] = [
+    "HARM_CATEGORY_HARASSMENT",
+    "HARM_CATEGORY_HATE_SPEECH",
+    "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+    "HARM_CATEGORY_DANGEROUS_CONTENT",
+];
+/// How much hotter a retry after a recitation stop samples.
+const RECITATION_TEMPERATURE_STEP: f32 = 0.5;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(super) struct SafetySetting {
+    category: &'static str,
+    threshold: &'static str,
+}
+
+/// The `safetySettings` of a request; empty when no threshold is configured,
+/// leaving the model's defaults in place.
+pub(super) fn safety_settings(threshold: Option<SafetyThreshold>) -> Vec<SafetySetting> {
+    let Some(threshold) = threshold else {
+        return Vec::new();
+    };
+    HARM_CATEGORIES
+        .iter()
+        .map(|category| SafetySetting {
+            category,
+            threshold: threshold.api_name(),
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct SafetyRating {
+    category: String,
+    probability: String,
+    blocked: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct PromptFeedback {
+    block_reason: Option<String>,
+    safety_ratings: Vec<SafetyRating>,
+}
+
+/// Why a response ended without usable text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// The prompt was rejected before anything was generated.
+    PromptBlocked {
+        reason: String,
+        categories: Vec<String>,
+    },
+    /// The output token limit was reached.
+    MaxTokens,
+    /// The output was withheld by the safety filters.
+    Safety { categories: Vec<String> },
+    /// The output was stopped for resembling existing content.
+    Recitation,
+    /// Any other reason, e.g. `BLOCKLIST` or `PROHIBITED_CONTENT`.
+    Other(String),
+}
+
+impl Stop {
+    pub(super) fn from_prompt_feedback(feedback: &PromptFeedback) -> Option<Stop> {
+        let reason = feedback.block_reason.as_deref()?;
+        Some(Stop::PromptBlocked {
+            reason: reason.to_string(),
+            categories: flagged_categories(&feedback.safety_ratings),
+        })
+    }
+
+    /// `None` for a candidate that finished normally.
+    pub(super) fn from_finish_reason(reason: &str, ratings: &[SafetyRating]) -> Option<Stop> {
+        match reason {
+            "STOP" | "FINISH_REASON_UNSPECIFIED" => None,
+            "MAX_TOKENS" => Some(Stop::MaxTokens),
+            "SAFETY" => Some(Stop::Safety {
+                categories: flagged_categories(ratings),
+            }),
+            "RECITATION" => Some(Stop::Recitation),
+            other => Some(Stop::Other(other.to_string())),
+        }
+    }
+
+    /// The settings to try once more with, where a change is likely to help.
+    /// Safety filters are never relaxed automatically.
+    pub(super) fn adjusted(&self, params: &GenerationConfig) -> Option<GenerationConfig> {
+        match self {
+            Stop::MaxTokens => {
+                let max_output_tokens = params.max_output_tokens?;
+                Some(GenerationConfig {
+                    max_output_tokens: Some(max_output_tokens.saturating_mul(2)),
+                    ..params.clone()
+                })
+            }
+            Stop::Recitation => Some(params.with_raised_temperature(RECITATION_TEMPERATURE_STEP)),
+            Stop::PromptBlocked { .. } | Stop::Safety { .. } | Stop::Other(_) => None,
+        }
+    }
+
+    /// A spinner notice for a retry with `adjusted` settings.
+    pub(super) fn retry_notice(&self, adjusted: &GenerationConfig) -> String {
+        match (self, adjusted.max_output_tokens, adjusted.temperature) {
+            (Stop::MaxTokens, Some(max_output_tokens), _) => format!(
+                "⚠️ Response cut off at the token limit, retrying with max_output_tokens = {}",
+                max_output_tokens
+            ),
+            (_, _, Some(temperature)) => format!(
+                "⚠️ Response stopped ({}), retrying at temperature {:.1}",
+                self.reason(),
+                temperature
+            ),
+            _ => format!("⚠️ Response stopped ({}), retrying", self.reason()),
+        }
+    }
+
+    fn reason(&self) -> &str {
+        match self {
+            Stop::PromptBlocked { reason, .. } => reason,
+            Stop::MaxTokens => "MAX_TOKENS",
+            Stop::Safety { .. } => "SAFETY",
+            Stop::Recitation => "RECITATION",
+            Stop::Other(reason) => reason,
+        }
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const SAFETY_HINT: &str = "If this is a false positive, relax generation.safety_threshold, e.g. to block_only_high.";
+        match self {
+            Stop::PromptBlocked { reason, categories } => write!(
+                f,
+                "The model blocked the prompt ({}{}). {}",
+                reason,
+                describe_categories(categories),
+                SAFETY_HINT
+            ),
+            Stop::MaxTokens => write!(
+                f,
+                "The response hit the output token limit (MAX_TOKENS) before a complete commit message was written. Raise generation.max_output_tokens."
+            ),
+            Stop::Safety { categories } => write!(
+                f,
+                "The model withheld the response for safety reasons (SAFETY{}). {}",
+                describe_categories(categories),
+                SAFETY_HINT
+            ),
+            Stop::Recitation => write!(
+                f,
+                "The model stopped the response because it resembled existing content (RECITATION)."
+            ),
+            Stop::Other(reason) => write!(f, "The model stopped the response early ({}).", reason),
+        }
+    }
+}
+
+impl std::error::Error for Stop {}
+
+/// The categories that were blocked or, failing that, rated a medium or high
+/// probability of harm, as readable names.
+fn flagged_categories(ratings: &[SafetyRating]) -> Vec<String> {
+    let blocked: Vec<&SafetyRating> = ratings.iter().filter(|r| r.blocked).collect();
+    let flagged = if blocked.is_empty() {
+        ratings
+            .iter()
+            .filter(|r| matches!(r.probability.as_str(), "MEDIUM" | "HIGH"))
+            .collect()
+    } else {
+        blocked
+    };
+    flagged
+        .iter()
+        .map(|rating| {
+            let name = rating
+                .category
+                .strip_prefix("HARM_CATEGORY_")
+                .unwrap_or(&rating.category);
+            name.to_lowercase().replace('_', " ")
+        })
+        .collect()
+}
+
+fn describe_categories(categories: &[String]) -> String {
+    if categories.is_empty() {
+        String::new()
+    } else {
+        format!("; flagged: {}", categories.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_reasons() -> anyhow::Result<()> {
+        let ratings: Vec<SafetyRating> = serde_json::from_str(
+            r#"[
+                {"category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE"},
+                {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH"}
+            ]"#,
+        )?;
+        assert_eq!(Stop::from_finish_reason("STOP", &ratings), None);
+        let stop = Stop::from_finish_reason("SAFETY", &ratings).unwrap();
+        assert_eq!(
+            stop.to_string(),
+            "The model withheld the response for safety reasons (SAFETY; flagged: dangerous content). If this is a false positive, relax generation.safety_threshold, e.g. to block_only_high."
+        );
+
+        let feedback: PromptFeedback = serde_json::from_str(r#"{"blockReason": "OTHER"}"#)?;
+        assert!(
+            Stop::from_prompt_feedback(&feedback)
+                .unwrap()
+                .to_string()
+                .starts_with("The model blocked the prompt (OTHER).")
+        );
+        assert!(Stop::from_prompt_feedback(&PromptFeedback::default()).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_adjusted_settings() {
+        let params = GenerationConfig {
+            max_output_tokens: Some(64),
+            ..GenerationConfig::default()
+        };
+        let adjusted = Stop::MaxTokens.adjusted(&params).unwrap();
+        assert_eq!(adjusted.max_output_tokens, Some(128));
+        assert_eq!(Stop::MaxTokens.adjusted(&GenerationConfig::default()), None);
+        assert_eq!(
+            Stop::Recitation.adjusted(&params).unwrap().temperature,
+            Some(1.5)
+        );
+        let safety = Stop::Safety {
+            categories: Vec::new(),
+        };
+        assert_eq!(safety.adjusted(&params), None);
+    }
+
+    #[test]
+    fn test_safety_settings() {
+        assert!(safety_settings(None).is_empty());
+        let settings = safety_settings(Some(SafetyThreshold::BlockOnlyHigh));
+        assert_eq!(settings.len(), HARM_CATEGORIES.len());
+        assert_eq!(
+            serde_json::to_value(&settings[0]).unwrap(),
+            serde_json::json!({"category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_ONLY_HIGH"})
+        );
+    }
+}
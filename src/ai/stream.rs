@@ -0,0 +1,175 @@
+//! Incremental parsing of streamed responses: server-sent events in, commit
+//! message suggestions out, each as soon as its line is complete.
+
+use super::{Usage, clean_suggestion_line};
+use std::collections::BTreeMap;
+
+/// A piece of streamed output for one candidate, identified by its index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delta<'a> {
+    Text(u32, &'a str),
+    /// The candidate will receive no more text.
+    Finished(u32),
+    /// The candidate hit the output token limit, so its open line is cut off.
+    CutOff(u32),
+    /// The tokens billed for the response so far.
+    Usage(Usage),
+}
+
+/// Splits a `text/event-stream` body into the `data` payloads of its events,
+/// accepting the body in arbitrary chunks.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Feeds a chunk of the body and returns the payload of every event it
+    /// completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            self.handle_line(line.trim_end_matches(['\n', '\r']), &mut events);
+        }
+        events
+    }
+
+    /// Returns the event left open when the body ended without a blank line.
+    pub fn finish(&mut self) -> Vec<String> {
+        let mut events = Vec::new();
+        let rest = std::mem::take(&mut self.buffer);
+        if !rest.is_empty() {
+            let line = String::from_utf8_lossy(&rest);
+            self.handle_line(line.trim_end_matches('\r'), &mut events);
+        }
+        self.handle_line("", &mut events);
+        events
+    }
+
+    fn handle_line(&mut self, line: &str, events: &mut Vec<String>) {
+        if line.is_empty() {
+            if !self.data.is_empty() {
+                events.push(self.data.join("\n"));
+                self.data.clear();
+            }
+        } else if let Some(value) = line.strip_prefix("data:") {
+            self.data
+                .push(value.strip_prefix(' ').unwrap_or(value).to_string());
+        }
+        // Comments and the `event`, `id` and `retry` fields carry nothing we use.
+    }
+}
+
+/// The line-by-line counterpart of `process_text_blocks`: collects streamed
+/// text per candidate and cleans each line once it is complete.
+#[derive(Debug, Default)]
+pub struct SuggestionParser {
+    open_lines: BTreeMap<u32, String>,
+}
+
+impl SuggestionParser {
+    /// Applies `delta` and returns the suggestions on every line it completed.
+    pub fn push(&mut self, delta: Delta) -> Vec<String> {
+        match delta {
+            Delta::Text(index, text) => {
+                let open_line = self.open_lines.entry(index).or_default();
+                open_line.push_str(text);
+                let Some(end) = open_line.rfind('\n') else {
+                    return Vec::new();
+                };
+                let complete: String = open_line.drain(..=end).collect();
+                complete.lines().filter_map(clean_suggestion_line).collect()
+            }
+            Delta::Finished(index) => self
+                .open_lines
+                .remove(&index)
+                .and_then(|line| clean_suggestion_line(&line))
+                .into_iter()
+                .collect(),
+            Delta::CutOff(index) => {
+                self.open_lines.remove(&index);
+                Vec::new()
+            }
+            Delta::Usage(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser_handles_split_events() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b": keep-alive\r\ndata: {\"a\"").is_empty());
+        assert_eq!(parser.push(b": 1}\r\n\r\ndata: [DO"), vec!["{\"a\": 1}"]);
+        assert_eq!(
+            parser.push(b"NE]\n\nevent: x\ndata: one\ndata: two\n"),
+            vec!["[DONE]"]
+        );
+        assert_eq!(parser.finish(), vec!["one\ntwo"]);
+    }
+
+    #[test]
+    fn test_sse_parser_keeps_multibyte_characters_split_across_chunks() {
+        let mut parser = SseParser::default();
+        let event = "data: fix: Handle café names\n\n".as_bytes();
+        let split = event.iter().position(|&b| b == 0xc3).unwrap() + 1;
+        assert!(parser.push(&event[..split]).is_empty());
+        assert_eq!(parser.push(&event[split..]), vec!["fix: Handle café names"]);
+    }
+
+    #[test]
+    fn test_suggestion_parser_emits_complete_lines_per_candidate() {
+        let mut parser = SuggestionParser::default();
+        assert!(
+            parser
+                .push(Delta::Text(0, "Here are some options:\n1. feat: Add "))
+                .is_empty()
+        );
+        assert!(parser.push(Delta::Text(1, "fix: Correct")).is_empty());
+        assert_eq!(
+            parser.push(Delta::Text(0, "cache\n- docs: Update")),
+            vec!["feat: Add cache"]
+        );
+        assert_eq!(
+            parser.push(Delta::Text(1, " typo\n")),
+            vec!["fix: Correct typo"]
+        );
+        assert!(parser.push(Delta::Finished(1)).is_empty());
+        assert_eq!(
+            parser.push(Delta::Text(0, " readme\n```")),
+            vec!["docs: Update readme"]
+        );
+        assert!(parser.push(Delta::Finished(0)).is_empty());
+    }
+
+    #[test]
+    fn test_suggestion_parser_flushes_finished_candidate() {
+        let mut parser = SuggestionParser::default();
+        assert!(
+            parser
+                .push(Delta::Text(2, "refactor: Split parser"))
+                .is_empty()
+        );
+        assert_eq!(
+            parser.push(Delta::Finished(2)),
+            vec!["refactor: Split parser"]
+        );
+    }
+
+    #[test]
+    fn test_suggestion_parser_drops_cut_off_line() {
+        let mut parser = SuggestionParser::default();
+        assert_eq!(
+            parser.push(Delta::Text(0, "feat: Add cache\nfix: Handle empty diff")),
+            vec!["feat: Add cache"]
+        );
+        assert!(parser.push(Delta::CutOff(0)).is_empty());
+    }
+}
@@ -0,0 +1,462 @@
+//! API key storage and lookup. Keys are looked up in this order, and the
+//! first source that has one wins:
+//!
+//! 1. the provider's environment variable (`api_key_env`, e.g. `GEMINI_API_KEY`);
+//! 2. the output of `api_key_cmd`, e.g. `pass show gemini`;
+//! 3. the OS keyring (Secret Service, macOS Keychain, Windows Credential Manager);
+//! 4. the credentials file written by `auth login` when no keyring is available.
+
+use crate::config::{Config, Provider};
+use crate::redact;
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Service name the keys are stored under in the OS keyring.
+pub const KEYRING_SERVICE: &str = "ai-commit";
+const CREDENTIALS_FILE_NAME: &str = "credentials.toml";
+
+/// A place an API key can come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySource {
+    EnvVar,
+    Command,
+    Keyring,
+    CredentialsFile,
+}
+
+impl KeySource {
+    /// Every source, in resolution order.
+    pub const ORDER: [KeySource; 4] = [
+        KeySource::EnvVar,
+        KeySource::Command,
+        KeySource::Keyring,
+        KeySource::CredentialsFile,
+    ];
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KeySource::EnvVar => "environment variable",
+            KeySource::Command => "api_key_cmd",
+            KeySource::Keyring => "OS keyring",
+            KeySource::CredentialsFile => "credentials file",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What one source had to say about a provider's key.
+#[derive(Debug)]
+pub struct SourceStatus {
+    pub source: KeySource,
+    /// Which variable, command, keyring entry or file was consulted; `None`
+    /// when the source is not configured.
+    pub location: Option<String>,
+    pub key: Result<Option<String>>,
+}
+
+pub fn default_api_key_env_var(provider: Provider) -> &'static str {
+    match provider {
+        Provider::Gemini => "GEMINI_API_KEY",
+        Provider::OpenAi => "OPENAI_API_KEY",
+    }
+}
+
+pub fn api_key_env_var(provider: Provider, config: &Config) -> &str {
+    config
+        .provider(provider)
+        .api_key_env
+        .as_deref()
+        .unwrap_or(default_api_key_env_var(provider))
+}
+
+/// `$AI_COMMIT_CREDENTIALS` if set, otherwise `ai-commit/credentials.toml` in
+/// the platform config directory, next to the global config file.
+pub fn credentials_path() -> Option<PathBuf> {
+    if let Ok(path) = env::var("AI_COMMIT_CREDENTIALS") {
+        return Some(PathBuf::from(path));
+    }
+    dirs::config_dir().map(|dir| dir.join("ai-commit").join(CREDENTIALS_FILE_NAME))
+}
+
+/// Shows enough of a key to tell keys apart without revealing it.
+pub fn mask(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+fn location(provider: Provider, config: &Config, source: KeySource) -> Option<String> {
+    match source {
+        KeySource::EnvVar => Some(format!("${}", api_key_env_var(provider, config))),
+        KeySource::Command => config
+            .provider(provider)
+            .api_key_cmd
+            .as_ref()
+            .map(|cmd| format!("`{}`", cmd)),
+        KeySource::Keyring => {
+            if cfg!(feature = "keyring") {
+                Some(format!("{}/{}", KEYRING_SERVICE, provider.name()))
+            } else {
+                None
+            }
+        }
+        KeySource::CredentialsFile => credentials_path().map(|path| path.display().to_string()),
+    }
+}
+
+fn lookup(provider: Provider, config: &Config, source: KeySource) -> Result<Option<String>> {
+    match source {
+        KeySource::EnvVar => Ok(env::var(api_key_env_var(provider, config))
+            .ok()
+            .filter(|key| !key.trim().is_empty())),
+        KeySource::Command => match &config.provider(provider).api_key_cmd {
+            Some(cmd) => cached_api_key_cmd(cmd).map(Some),
+            None => Ok(None),
+        },
+        KeySource::Keyring => keyring_get(provider),
+        KeySource::CredentialsFile => match credentials_path() {
+            Some(path) => Ok(read_credentials(&path)?.remove(provider.name())),
+            None => Ok(None),
+        },
+    }
+}
+
+/// Consults every source, for `auth status`.
+pub fn status(provider: Provider, config: &Config) -> Vec<SourceStatus> {
+    KeySource::ORDER
+        .iter()
+        .map(|&source| SourceStatus {
+            source,
+            location: location(provider, config, source),
+            key: lookup(provider, config, source),
+        })
+        .collect()
+}
+
+/// Returns the key from the first source that has one. A failing
+/// `api_key_cmd` or unreadable credentials file is an error; an unavailable
+/// keyring is skipped.
+pub fn resolve_api_key(provider: Provider, config: &Config) -> Result<String> {
+    for source in KeySource::ORDER {
+        match lookup(provider, config, source) {
+            Ok(Some(key)) => {
+                redact::register(&key);
+                return Ok(key);
+            }
+            Ok(None) => {}
+            Err(_) if source == KeySource::Keyring => {}
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "Failed to read the {} API key from the {}",
+                        provider.name(),
+                        source
+                    )
+                });
+            }
+        }
+    }
+    bail!(
+        "No {} API key found. Run 'ai-commit auth login --provider {}', set the {} environment variable, or configure providers.{}.api_key_cmd.",
+        provider.name(),
+        provider.name(),
+        api_key_env_var(provider, config),
+        provider.name()
+    )
+}
+
+/// Keys printed by each `api_key_cmd`, so a command that prompts or unlocks
+/// a vault runs once per process rather than once per request.
+static COMMAND_KEYS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Returns the key `cmd` printed earlier in this process, or runs it.
+fn cached_api_key_cmd(cmd: &str) -> Result<String> {
+    let mut keys = COMMAND_KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(key) = keys.get(cmd) {
+        return Ok(key.clone());
+    }
+    let key = run_api_key_cmd(cmd)?;
+    keys.insert(cmd.to_string(), key.clone());
+    Ok(key)
+}
+
+/// Runs `cmd` through the shell and returns the first line it prints.
+fn run_api_key_cmd(cmd: &str) -> Result<String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", cmd]).output()
+    } else {
+        Command::new("sh").args(["-c", cmd]).output()
+    }
+    .with_context(|| format!("Failed to run api_key_cmd `{}`", cmd))?;
+    if !output.status.success() {
+        bail!(
+            "api_key_cmd `{}` failed with {}: {}",
+            cmd,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.lines().next().map(str::trim) {
+        Some(key) if !key.is_empty() => Ok(key.to_string()),
+        _ => bail!("api_key_cmd `{}` printed no key.", cmd),
+    }
+}
+
+fn read_credentials(path: &Path) -> Result<BTreeMap<String, String>> {
+    if !path.is_file() {
+        return Ok(BTreeMap::new());
+    }
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read credentials file {:?}", path))?;
+    toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse credentials file {:?}", path))
+}
+
+/// Writes the credentials file readable by the owner only.
+fn write_credentials(path: &Path, credentials: &BTreeMap<String, String>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    let contents = toml::to_string(credentials).context("Failed to serialize credentials")?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` only applies to new files; tighten an existing one too.
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+                .with_context(|| format!("Failed to restrict permissions of {:?}", path))?;
+        }
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to open credentials file {:?}", path))?;
+    std::io::Write::write_all(&mut file, contents.as_bytes())
+        .with_context(|| format!("Failed to write credentials file {:?}", path))
+}
+
+/// Where `store_api_key` put a key.
+#[derive(Debug, PartialEq)]
+pub enum Stored {
+    Keyring,
+    /// The credentials file, with the reason the keyring was not used if it
+    /// was tried.
+    File {
+        path: PathBuf,
+        keyring_error: Option<String>,
+    },
+}
+
+/// Saves a provider's key in the OS keyring, or in the credentials file when
+/// `to_file` is set or the keyring is unavailable.
+pub fn store_api_key(provider: Provider, key: &str, to_file: bool) -> Result<Stored> {
+    let keyring_error = if to_file {
+        None
+    } else {
+        match keyring_set(provider, key) {
+            Ok(()) => return Ok(Stored::Keyring),
+            Err(e) => Some(format!("{:#}", e)),
+        }
+    };
+    let Some(path) = credentials_path() else {
+        bail!("Cannot locate the config directory to store the credentials file.");
+    };
+    let mut credentials = read_credentials(&path)?;
+    credentials.insert(provider.name().to_string(), key.to_string());
+    write_credentials(&path, &credentials)?;
+    Ok(Stored::File {
+        path,
+        keyring_error,
+    })
+}
+
+/// Removes a provider's key from the keyring and the credentials file, and
+/// returns the sources it was removed from.
+pub fn delete_api_key(provider: Provider) -> Result<Vec<KeySource>> {
+    let mut removed = Vec::new();
+    if keyring_delete(provider)? {
+        removed.push(KeySource::Keyring);
+    }
+    if let Some(path) = credentials_path() {
+        let mut credentials = read_credentials(&path)?;
+        if credentials.remove(provider.name()).is_some() {
+            write_credentials(&path, &credentials)?;
+            removed.push(KeySource::CredentialsFile);
+        }
+    }
+    Ok(removed)
+}
+
+/// Runs a keyring operation on a thread of its own: the Secret Service client
+/// blocks on a private tokio runtime, which deadlocks inside ours.
+#[cfg(feature = "keyring")]
+fn with_keyring_entry<T: Send>(
+    provider: Provider,
+    operation: impl FnOnce(&keyring::Entry) -> keyring::Result<T> + Send,
+) -> keyring::Result<T> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| operation(&keyring::Entry::new(KEYRING_SERVICE, provider.name())?))
+            .join()
+            .unwrap_or_else(|_| {
+                Err(keyring::Error::PlatformFailure(
+                    "keyring thread panicked".into(),
+                ))
+            })
+    })
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_get(provider: Provider) -> Result<Option<String>> {
+    match with_keyring_entry(provider, |entry| entry.get_password()) {
+        Ok(key) => Ok(Some(key)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).context("OS keyring is unavailable"),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_set(provider: Provider, key: &str) -> Result<()> {
+    with_keyring_entry(provider, |entry| entry.set_password(key))
+        .context("OS keyring is unavailable")
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_delete(provider: Provider) -> Result<bool> {
+    match with_keyring_entry(provider, |entry| entry.delete_credential()) {
+        Ok(()) => Ok(true),
+        // Nothing to delete, or no keyring to delete it from.
+        Err(_) => Ok(false),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_get(_provider: Provider) -> Result<Option<String>> {
+    Ok(None)
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_set(_provider: Provider, _key: &str) -> Result<()> {
+    bail!("ai-commit was built without keyring support (the `keyring` feature).")
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_delete(_provider: Provider) -> Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("AIzaSyExampleKey1234"), "AIza…1234");
+        assert_eq!(mask("short"), "*****");
+    }
+
+    #[test]
+    fn test_credentials_file_round_trip() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("nested/credentials.toml");
+        let mut credentials = BTreeMap::new();
+        credentials.insert("gemini".to_string(), "gem-key".to_string());
+        credentials.insert("openai".to_string(), "sk-key".to_string());
+        write_credentials(&path, &credentials)?;
+        assert_eq!(read_credentials(&path)?, credentials);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        assert!(read_credentials(&temp_dir.path().join("missing.toml"))?.is_empty());
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_api_key_cmd() -> Result<()> {
+        assert_eq!(
+            run_api_key_cmd("printf 'cmd-key\\nsecond line'")?,
+            "cmd-key"
+        );
+        let message = format!(
+            "{:#}",
+            run_api_key_cmd("echo locked >&2; exit 3").unwrap_err()
+        );
+        assert!(message.contains("locked"), "{}", message);
+        assert!(run_api_key_cmd("true").is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolution_prefers_env_var_then_command() -> Result<()> {
+        let mut config = Config::default();
+        config.providers.openai.api_key_env = Some("AI_COMMIT_TEST_AUTH_KEY".to_string());
+        config.providers.openai.api_key_cmd = Some("echo from-cmd".to_string());
+
+        // SAFETY: the variable is unique to this test.
+        unsafe { env::remove_var("AI_COMMIT_TEST_AUTH_KEY") };
+        assert_eq!(resolve_api_key(Provider::OpenAi, &config)?, "from-cmd");
+        unsafe { env::set_var("AI_COMMIT_TEST_AUTH_KEY", "from-env") };
+        assert_eq!(resolve_api_key(Provider::OpenAi, &config)?, "from-env");
+
+        let statuses = status(Provider::OpenAi, &config);
+        let sources: Vec<KeySource> = statuses.iter().map(|s| s.source).collect();
+        assert_eq!(sources, KeySource::ORDER);
+        assert_eq!(
+            statuses[0].location.as_deref(),
+            Some("$AI_COMMIT_TEST_AUTH_KEY")
+        );
+        assert_eq!(statuses[1].location.as_deref(), Some("`echo from-cmd`"));
+        assert_eq!(
+            statuses[1].key.as_ref().unwrap().as_deref(),
+            Some("from-cmd")
+        );
+
+        unsafe { env::remove_var("AI_COMMIT_TEST_AUTH_KEY") };
+        config.providers.openai.api_key_cmd = Some("exit 1".to_string());
+        let message = format!(
+            "{:#}",
+            resolve_api_key(Provider::OpenAi, &config).unwrap_err()
+        );
+        assert!(message.contains("api_key_cmd"), "{}", message);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_api_key_cmd_runs_once_per_process() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let runs = temp_dir.path().join("runs");
+        let mut config = Config::default();
+        config.providers.gemini.api_key_env = Some("AI_COMMIT_TEST_CACHED_KEY".to_string());
+        config.providers.gemini.api_key_cmd =
+            Some(format!("echo run >> {:?}; echo cached-key", runs));
+
+        for _ in 0..3 {
+            assert_eq!(resolve_api_key(Provider::Gemini, &config)?, "cached-key");
+        }
+        assert_eq!(fs::read_to_string(&runs)?, "run\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use crate::config::CacheConfig;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub created_at: u64,
+    /// `provider/model` of the model that produced the candidates.
+    pub model: String,
+    /// Raw candidate texts as returned by the model, before parsing and validation.
+    pub candidates: Vec<String>,
+}
+
+/// On-disk cache of model responses, one JSON file per key.
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Hashes everything that influences a response into a stable hex key.
+pub fn cache_key(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        ResponseCache { dir, ttl }
+    }
+
+    /// Builds the cache described by the config, or `None` if caching is
+    /// disabled or no cache directory is available.
+    pub fn from_config(config: &CacheConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let dir = match &config.dir {
+            Some(dir) => dir.clone(),
+            None => dirs::cache_dir()?.join("ai-commit").join("responses"),
+        };
+        Some(ResponseCache::new(
+            dir,
+            Duration::from_secs(config.ttl_secs),
+        ))
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// Returns the entry for `key` if present and not expired. Unreadable or
+    /// expired entries are treated as misses and removed.
+    pub fn get(&self, key: &str) -> Option<CacheEntry> {
+        let path = self.entry_path(key);
+        let contents = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<CacheEntry>(&contents) {
+            Ok(entry) if now_secs().saturating_sub(entry.created_at) < self.ttl.as_secs() => {
+                Some(entry)
+            }
+            _ => {
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    pub fn put(&self, key: &str, model: &str, candidates: &[String]) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory {:?}", self.dir))?;
+        let entry = CacheEntry {
+            created_at: now_secs(),
+            model: model.to_string(),
+            candidates: candidates.to_vec(),
+        };
+        let path = self.entry_path(key);
+        let contents = serde_json::to_string(&entry).context("Failed to serialize cache entry")?;
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write cache entry {:?}", path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_cache_key_is_stable_and_unambiguous() {
+        let key = cache_key(&["prompt", "gemini/model"]);
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, cache_key(&["prompt", "gemini/model"]));
+        assert_ne!(key, cache_key(&["prompt", "openai/model"]));
+        assert_ne!(cache_key(&["ab", "c"]), cache_key(&["a", "bc"]));
+    }
+
+    #[test]
+    fn test_put_then_get_roundtrip() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let cache = ResponseCache::new(temp_dir.path().join("nested"), Duration::from_secs(60));
+        let candidates = vec!["feat: Add caching of model responses".to_string()];
+        assert_eq!(cache.get("key"), None);
+        cache.put("key", "gemini/model", &candidates)?;
+        let entry = cache.get("key").expect("entry should be cached");
+        assert_eq!(entry.model, "gemini/model");
+        assert_eq!(entry.candidates, candidates);
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired_and_corrupt_entries_are_misses() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let expired = ResponseCache::new(temp_dir.path().to_path_buf(), Duration::from_secs(0));
+        expired.put("old", "gemini/model", &["fix: Something".to_string()])?;
+        assert_eq!(expired.get("old"), None);
+        assert!(!temp_dir.path().join("old.json").exists());
+
+        let cache = ResponseCache::new(temp_dir.path().to_path_buf(), Duration::from_secs(60));
+        fs::write(temp_dir.path().join("corrupt.json"), "{not json")?;
+        assert_eq!(cache.get("corrupt"), None);
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disabled_config_has_no_cache() {
+        let config = CacheConfig {
+            enabled: false,
+            ..CacheConfig::default()
+        };
+        assert!(ResponseCache::from_config(&config).is_none());
+    }
+}
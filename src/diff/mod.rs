@@ -1,3 +1,4 @@
+#[allow(clippy::manual_strip)]
 pub fn preprocess_diff_for_ai(raw_diff: &str) -> String {
     let mut processed_lines = Vec::new();
     for line in raw_diff.lines() {
@@ -30,6 +31,55 @@ pub fn preprocess_diff_for_ai(raw_diff: &str) -> String {
     processed_lines.join("\n")
 }
 
+/// Shortens `diff` to at most `max_chars` characters, cutting at a line
+/// boundary and noting how many lines and which files were dropped.
+pub fn truncate_diff(diff: &str, max_chars: usize) -> String {
+    if diff.chars().count() <= max_chars {
+        return diff.to_string();
+    }
+    let mut kept = String::new();
+    let mut kept_chars = 0;
+    let mut kept_lines = 0;
+    let lines: Vec<&str> = diff.lines().collect();
+    for line in &lines {
+        let line_chars = line.chars().count() + 1;
+        if kept_chars + line_chars > max_chars {
+            break;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+        kept_chars += line_chars;
+        kept_lines += 1;
+    }
+    let (shown, omitted) = lines.split_at(kept_lines);
+    let mut files = Vec::new();
+    if omitted
+        .first()
+        .is_some_and(|line| !line.starts_with("diff --git "))
+        && let Some(path) = shown.iter().rev().find_map(|line| header_path(line))
+    {
+        files.push(path);
+    }
+    files.extend(omitted.iter().filter_map(|line| header_path(line)));
+    kept.push_str(&format!(
+        "[... diff truncated: {} more lines omitted ...]",
+        omitted.len()
+    ));
+    if !files.is_empty() {
+        kept.push_str(&format!(
+            "\n[Files cut short or left out of the diff: {}]",
+            files.join(", ")
+        ));
+    }
+    kept
+}
+
+/// The new path named by a `diff --git a/<old> b/<new>` header line.
+fn header_path(line: &str) -> Option<&str> {
+    let paths = line.strip_prefix("diff --git ")?;
+    paths.rsplit_once(" b/").map(|(_, path)| path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -191,4 +241,43 @@ mod tests {
                         [ADDED_LINE]: this line has a + plus and a - minus sign.";
         assert_eq!(preprocess_diff_for_ai(raw_diff), expected);
     }
+
+    #[test]
+    fn test_truncate_diff_cuts_at_line_boundary() {
+        let diff = "+first line\n+second line\n+third line";
+        assert_eq!(truncate_diff(diff, 100), diff);
+        assert_eq!(
+            truncate_diff(diff, 20),
+            "+first line\n[... diff truncated: 2 more lines omitted ...]"
+        );
+        assert_eq!(
+            truncate_diff(diff, 3),
+            "[... diff truncated: 3 more lines omitted ...]"
+        );
+    }
+
+    #[test]
+    fn test_truncate_diff_lists_files_cut_short_or_left_out() {
+        let diff = "diff --git a/src/lib.rs b/src/lib.rs\n\
+                    [ADDED_LINE]: fn kept() {}\n\
+                    [ADDED_LINE]: fn cut() {}\n\
+                    diff --git a/README.md b/README.md\n\
+                    [ADDED_LINE]: docs";
+        assert_eq!(
+            truncate_diff(diff, 70),
+            "diff --git a/src/lib.rs b/src/lib.rs\n\
+             [ADDED_LINE]: fn kept() {}\n\
+             [... diff truncated: 3 more lines omitted ...]\n\
+             [Files cut short or left out of the diff: src/lib.rs, README.md]"
+        );
+        let first_file_only = diff.lines().take(3).collect::<Vec<_>>().join("\n");
+        assert_eq!(
+            truncate_diff(diff, first_file_only.len() + 1),
+            format!(
+                "{}\n[... diff truncated: 2 more lines omitted ...]\n\
+                 [Files cut short or left out of the diff: README.md]",
+                first_file_only
+            )
+        );
+    }
 }
@@ -0,0 +1,609 @@
+use crate::ai::{self, GenerationRequest};
+use crate::cache;
+use crate::config::Config;
+use crate::diff;
+use crate::git::{self, StagedChangesSummary};
+use crate::prompt::{self, Prompt, PromptInput, PromptTemplate};
+use crate::validate::ConventionalCommit;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Predicted type recorded for cases where no valid suggestion was produced.
+const NO_PREDICTION: &str = "(none)";
+
+/// One line of an evaluation dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EvalCase {
+    pub id: String,
+    /// Raw `git diff` output for the change.
+    pub diff: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub binary_file_changes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub structure_changes: Vec<String>,
+    pub expected_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_subject: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaseResult {
+    pub id: String,
+    pub expected_type: String,
+    pub predicted_type: String,
+    pub type_correct: bool,
+    /// `None` when the case has no expected scope.
+    #[serde(default)]
+    pub scope_correct: Option<bool>,
+    /// Whether a suggestion passed validation.
+    pub valid: bool,
+    #[serde(default)]
+    pub suggestion: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub latency_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EvalSummary {
+    pub total: usize,
+    pub type_correct: usize,
+    pub type_accuracy: f64,
+    pub scope_cases: usize,
+    pub scope_correct: usize,
+    pub valid: usize,
+    pub validation_pass_rate: f64,
+    pub latency_p50_ms: u64,
+    pub latency_p95_ms: u64,
+    pub latency_mean_ms: u64,
+    /// `expected type -> predicted type -> count`.
+    pub confusion: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+/// Everything needed to compare one evaluation run with another.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EvalReport {
+    #[serde(default)]
+    pub label: Option<String>,
+    pub created_at: u64,
+    /// Hash of the prompt text with empty inputs; changes whenever the
+    /// instructions sent to the model change.
+    pub prompt_fingerprint: String,
+    pub models: Vec<String>,
+    pub summary: EvalSummary,
+    pub cases: Vec<CaseResult>,
+}
+
+pub fn load_dataset(path: &Path) -> Result<Vec<EvalCase>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read eval dataset {:?}", path))?;
+    let mut cases = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let case: EvalCase = serde_json::from_str(line)
+            .with_context(|| format!("Invalid eval case on line {} of {:?}", index + 1, path))?;
+        cases.push(case);
+    }
+    if cases.is_empty() {
+        bail!("Eval dataset {:?} contains no cases.", path);
+    }
+    Ok(cases)
+}
+
+pub fn save_dataset(path: &Path, cases: &[EvalCase]) -> Result<()> {
+    let mut contents = String::new();
+    for case in cases {
+        contents.push_str(&serde_json::to_string(case)?);
+        contents.push('\n');
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write eval dataset {:?}", path))
+}
+
+/// Builds cases from the Conventional Commit subjects in a repository's
+/// history. Commits that do not follow the convention are skipped. Historical
+/// commits carry no staged-change summary, so only the diff is used.
+pub fn harvest_dataset(repo_path: &Path, max_cases: usize) -> Result<Vec<EvalCase>> {
+    // Non-conventional commits are skipped, so look further back than needed.
+    let history = git::get_commit_subjects(repo_path, max_cases.saturating_mul(4))?;
+    let mut cases = Vec::new();
+    for (sha, subject) in history {
+        if cases.len() >= max_cases {
+            break;
+        }
+        let Some(commit) = ConventionalCommit::parse(&subject) else {
+            continue;
+        };
+        if !prompt::is_known_commit_type(&commit.commit_type) {
+            continue;
+        }
+        let diff = git::get_commit_diff(repo_path, &sha)?;
+        if diff.trim().is_empty() {
+            continue;
+        }
+        cases.push(EvalCase {
+            id: sha.chars().take(12).collect(),
+            diff,
+            binary_file_changes: Vec::new(),
+            structure_changes: Vec::new(),
+            expected_type: commit.commit_type,
+            expected_scope: commit.scope,
+            reference_subject: Some(subject),
+        });
+    }
+    if cases.is_empty() {
+        bail!("No Conventional Commits with a diff found in the repository history.");
+    }
+    Ok(cases)
+}
+
+pub fn prompt_fingerprint(template: &PromptTemplate, guidelines: Option<&str>) -> Result<String> {
+    let prompt = template.render(&PromptInput {
+        diff: "",
+        changes_summary: &StagedChangesSummary::default(),
+        num_suggestions: 1,
+        previous_message: None,
+        branch: None,
+        guidelines,
+    })?;
+    Ok(cache::cache_key(&[&prompt.text()])
+        .chars()
+        .take(12)
+        .collect())
+}
+
+fn case_prompt(
+    case: &EvalCase,
+    template: &PromptTemplate,
+    guidelines: Option<&str>,
+) -> Result<Prompt> {
+    let preprocessed_diff = diff::preprocess_diff_for_ai(&case.diff);
+    let summary = StagedChangesSummary {
+        binary_file_changes: case.binary_file_changes.clone(),
+        structure_changes: case.structure_changes.clone(),
+        ..StagedChangesSummary::default()
+    };
+    template.render(&PromptInput {
+        diff: &preprocessed_diff,
+        changes_summary: &summary,
+        num_suggestions: 1,
+        previous_message: None,
+        branch: None,
+        guidelines,
+    })
+}
+
+async fn evaluate_case(case: &EvalCase, prompt: &Prompt, config: &Config) -> CaseResult {
+    let request = GenerationRequest {
+        prompt,
+        num_candidates: 1,
+        regeneration: 0,
+    };
+    let started = Instant::now();
+    let generation = ai::generate_text(&request, config, &|_| {}).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let mut result = CaseResult {
+        id: case.id.clone(),
+        expected_type: case.expected_type.clone(),
+        predicted_type: NO_PREDICTION.to_string(),
+        type_correct: false,
+        scope_correct: case.expected_scope.as_ref().map(|_| false),
+        valid: false,
+        suggestion: None,
+        model: None,
+        error: None,
+        latency_ms,
+    };
+    match generation {
+        Ok(generation) => {
+            result.model = Some(generation.model.to_string());
+            let suggestion = generation.suggestions.into_iter().next();
+            if let Some(commit) = suggestion.as_deref().and_then(ConventionalCommit::parse) {
+                result.valid = true;
+                result.type_correct = commit.commit_type == case.expected_type;
+                result.scope_correct = case
+                    .expected_scope
+                    .as_ref()
+                    .map(|expected| commit.scope.as_ref() == Some(expected));
+                result.predicted_type = commit.commit_type;
+            }
+            result.suggestion = suggestion;
+        }
+        Err(e) => result.error = Some(format!("{:#}", e)),
+    }
+    result
+}
+
+fn percentile(sorted: &[u64], percent: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank.min(sorted.len()) - 1]
+}
+
+fn ratio(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+pub fn summarize(results: &[CaseResult]) -> EvalSummary {
+    let total = results.len();
+    let type_correct = results.iter().filter(|r| r.type_correct).count();
+    let valid = results.iter().filter(|r| r.valid).count();
+    let scope_cases = results.iter().filter(|r| r.scope_correct.is_some()).count();
+    let scope_correct = results
+        .iter()
+        .filter(|r| r.scope_correct == Some(true))
+        .count();
+
+    let mut latencies: Vec<u64> = results.iter().map(|r| r.latency_ms).collect();
+    latencies.sort_unstable();
+    let latency_mean_ms = if total == 0 {
+        0
+    } else {
+        latencies.iter().sum::<u64>() / total as u64
+    };
+
+    let mut confusion: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for result in results {
+        *confusion
+            .entry(result.expected_type.clone())
+            .or_default()
+            .entry(result.predicted_type.clone())
+            .or_default() += 1;
+    }
+
+    EvalSummary {
+        total,
+        type_correct,
+        type_accuracy: ratio(type_correct, total),
+        scope_cases,
+        scope_correct,
+        valid,
+        validation_pass_rate: ratio(valid, total),
+        latency_p50_ms: percentile(&latencies, 50),
+        latency_p95_ms: percentile(&latencies, 95),
+        latency_mean_ms,
+        confusion,
+    }
+}
+
+/// Runs every case through the prompt template, with the project guidelines,
+/// and the configured model chain. Caching is always disabled so each run measures the models
+/// themselves. `on_case` is called after each case with its position and result.
+pub async fn run_eval(
+    cases: &[EvalCase],
+    template: &PromptTemplate,
+    guidelines: Option<&str>,
+    config: &Config,
+    label: Option<String>,
+    on_case: &dyn Fn(usize, &CaseResult),
+) -> Result<EvalReport> {
+    let mut config = config.clone();
+    config.cache.enabled = false;
+    let prompt_fingerprint = prompt_fingerprint(template, guidelines)?;
+
+    let mut results = Vec::new();
+    for (index, case) in cases.iter().enumerate() {
+        let prompt_text = case_prompt(case, template, guidelines)?;
+        let result = evaluate_case(case, &prompt_text, &config).await;
+        on_case(index, &result);
+        results.push(result);
+    }
+
+    Ok(EvalReport {
+        label,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        prompt_fingerprint,
+        models: config.models.iter().map(|m| m.to_string()).collect(),
+        summary: summarize(&results),
+        cases: results,
+    })
+}
+
+pub fn save_report(path: &Path, report: &EvalReport) -> Result<()> {
+    let contents = serde_json::to_string_pretty(report).context("Failed to serialize report")?;
+    fs::write(path, contents).with_context(|| format!("Failed to write eval report {:?}", path))
+}
+
+pub fn load_report(path: &Path) -> Result<EvalReport> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read eval report {:?}", path))?;
+    serde_json::from_str(&contents).with_context(|| format!("Invalid eval report {:?}", path))
+}
+
+fn percent(value: f64) -> String {
+    format!("{:.1}%", value * 100.0)
+}
+
+fn format_confusion(confusion: &BTreeMap<String, BTreeMap<String, usize>>) -> String {
+    let predicted: BTreeSet<&String> = confusion.values().flat_map(|row| row.keys()).collect();
+    let width = confusion
+        .keys()
+        .chain(predicted.iter().copied())
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(8);
+
+    let mut out = format!("{:width$}", "expected", width = width);
+    for name in &predicted {
+        let _ = write!(out, " {:>width$}", name, width = width);
+    }
+    out.push('\n');
+    for (expected, row) in confusion {
+        let _ = write!(out, "{:width$}", expected, width = width);
+        for name in &predicted {
+            let count = row.get(*name).copied().unwrap_or(0);
+            let _ = write!(out, " {:>width$}", count, width = width);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+pub fn format_report(report: &EvalReport) -> String {
+    let summary = &report.summary;
+    let mut out = format!(
+        "📊 Evaluated {} case(s) with {} (prompt {}{})\n",
+        summary.total,
+        report.models.join(" → "),
+        report.prompt_fingerprint,
+        report
+            .label
+            .as_deref()
+            .map(|label| format!(", label '{}'", label))
+            .unwrap_or_default()
+    );
+    let _ = writeln!(
+        out,
+        "Type accuracy:        {} ({}/{})",
+        percent(summary.type_accuracy),
+        summary.type_correct,
+        summary.total
+    );
+    if summary.scope_cases > 0 {
+        let _ = writeln!(
+            out,
+            "Scope accuracy:       {} ({}/{})",
+            percent(ratio(summary.scope_correct, summary.scope_cases)),
+            summary.scope_correct,
+            summary.scope_cases
+        );
+    }
+    let _ = writeln!(
+        out,
+        "Validation pass rate: {} ({}/{})",
+        percent(summary.validation_pass_rate),
+        summary.valid,
+        summary.total
+    );
+    let _ = writeln!(
+        out,
+        "Latency:              p50 {} ms, p95 {} ms, mean {} ms",
+        summary.latency_p50_ms, summary.latency_p95_ms, summary.latency_mean_ms
+    );
+    let _ = write!(
+        out,
+        "\nConfusion matrix (rows: expected, columns: predicted):\n{}",
+        format_confusion(&summary.confusion)
+    );
+    out
+}
+
+/// Describes how `report` moved relative to `baseline`.
+pub fn format_comparison(report: &EvalReport, baseline: &EvalReport) -> String {
+    let mut out = format!(
+        "Compared with baseline {} (prompt {}, {}):\n",
+        baseline.label.as_deref().unwrap_or("run"),
+        baseline.prompt_fingerprint,
+        baseline.models.join(" → ")
+    );
+    let points = |now: f64, before: f64| format!("{:+.1} pts", (now - before) * 100.0);
+    let _ = writeln!(
+        out,
+        "Type accuracy:        {}",
+        points(report.summary.type_accuracy, baseline.summary.type_accuracy)
+    );
+    let _ = writeln!(
+        out,
+        "Validation pass rate: {}",
+        points(
+            report.summary.validation_pass_rate,
+            baseline.summary.validation_pass_rate
+        )
+    );
+    let _ = writeln!(
+        out,
+        "Latency p50:          {:+} ms",
+        report.summary.latency_p50_ms as i64 - baseline.summary.latency_p50_ms as i64
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockLlmServer, MockResponse};
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    fn eval_case(id: &str, expected_type: &str, expected_scope: Option<&str>) -> EvalCase {
+        EvalCase {
+            id: id.to_string(),
+            diff: "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-old\n+new\n"
+                .to_string(),
+            binary_file_changes: Vec::new(),
+            structure_changes: Vec::new(),
+            expected_type: expected_type.to_string(),
+            expected_scope: expected_scope.map(str::to_string),
+            reference_subject: None,
+        }
+    }
+
+    fn case_result(expected: &str, predicted: &str, latency_ms: u64) -> CaseResult {
+        CaseResult {
+            id: "case".to_string(),
+            expected_type: expected.to_string(),
+            predicted_type: predicted.to_string(),
+            type_correct: expected == predicted,
+            scope_correct: None,
+            valid: predicted != NO_PREDICTION,
+            suggestion: None,
+            model: None,
+            error: None,
+            latency_ms,
+        }
+    }
+
+    #[test]
+    fn test_dataset_roundtrip_skips_blank_lines() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("cases.jsonl");
+        let cases = vec![
+            eval_case("1", "fix", None),
+            eval_case("2", "feat", Some("cli")),
+        ];
+        save_dataset(&path, &cases)?;
+        let contents = fs::read_to_string(&path)? + "\n\n";
+        fs::write(&path, contents)?;
+        assert_eq!(load_dataset(&path)?, cases);
+
+        fs::write(&path, "{\"id\": \"1\"}\n")?;
+        let error = load_dataset(&path).unwrap_err().to_string();
+        assert!(error.contains("line 1"));
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_summarize_counts_and_confusion() {
+        let results = vec![
+            case_result("feat", "feat", 100),
+            case_result("feat", "fix", 300),
+            case_result("fix", "fix", 200),
+            case_result("docs", NO_PREDICTION, 400),
+        ];
+        let summary = summarize(&results);
+        assert_eq!(summary.total, 4);
+        assert_eq!(summary.type_correct, 2);
+        assert_eq!(summary.type_accuracy, 0.5);
+        assert_eq!(summary.validation_pass_rate, 0.75);
+        assert_eq!(summary.latency_p50_ms, 200);
+        assert_eq!(summary.latency_p95_ms, 400);
+        assert_eq!(summary.latency_mean_ms, 250);
+        assert_eq!(summary.confusion["feat"]["fix"], 1);
+        assert_eq!(summary.confusion["docs"][NO_PREDICTION], 1);
+
+        let matrix = format_confusion(&summary.confusion);
+        let header = matrix.lines().next().unwrap();
+        assert!(header.starts_with("expected"));
+        assert!(header.contains("(none)") && header.contains("feat") && header.contains("fix"));
+    }
+
+    #[test]
+    fn test_harvest_dataset_keeps_conventional_commits() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(path)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        git(&["init", "-b", "main"]);
+        git(&["config", "user.name", "Test User"]);
+        git(&["config", "user.email", "test@example.com"]);
+        fs::write(path.join("a.txt"), "one\n")?;
+        git(&["add", "a.txt"]);
+        git(&["commit", "-m", "Initial import"]);
+        fs::write(path.join("a.txt"), "two\n")?;
+        git(&["commit", "-am", "fix(core): Correct the sample value"]);
+        fs::write(path.join("b.md"), "# Docs\n")?;
+        git(&["add", "b.md"]);
+        git(&["commit", "-m", "docs: Add a docs page"]);
+
+        let cases = harvest_dataset(path, 10)?;
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].expected_type, "docs");
+        assert_eq!(cases[1].expected_type, "fix");
+        assert_eq!(cases[1].expected_scope.as_deref(), Some("core"));
+        assert_eq!(
+            cases[1].reference_subject.as_deref(),
+            Some("fix(core): Correct the sample value")
+        );
+        assert!(cases[1].diff.contains("+two"));
+        assert_eq!(harvest_dataset(path, 1)?.len(), 1);
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_eval_against_mock_server() -> Result<()> {
+        let server = MockLlmServer::start(vec![
+            MockResponse::gemini(&["fix: Replace the old value"]),
+            MockResponse::gemini(&["fix: Update the stored value"]),
+            MockResponse::new(400, "{}"),
+        ])?;
+        let config = server.config(&["gemini/test-model"]);
+        let cases = vec![
+            eval_case("1", "fix", None),
+            eval_case("2", "refactor", None),
+            eval_case("3", "fix", None),
+        ];
+
+        let template = PromptTemplate::builtin();
+        let report = run_eval(
+            &cases,
+            &template,
+            None,
+            &config,
+            Some("baseline".to_string()),
+            &|_, _| {},
+        )
+        .await?;
+
+        assert_eq!(report.models, vec!["gemini/test-model"]);
+        assert_eq!(
+            report.prompt_fingerprint,
+            prompt_fingerprint(&template, None)?
+        );
+        assert_eq!(report.summary.type_correct, 1);
+        assert_eq!(report.summary.valid, 2);
+        assert_eq!(report.summary.confusion["refactor"]["fix"], 1);
+        assert_eq!(report.summary.confusion["fix"][NO_PREDICTION], 1);
+        assert!(report.cases[2].error.is_some());
+
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("report.json");
+        save_report(&path, &report)?;
+        let baseline = load_report(&path)?;
+        assert_eq!(baseline, report);
+        let comparison = format_comparison(&report, &baseline);
+        assert!(comparison.contains("Type accuracy:        +0.0 pts"));
+        assert!(format_report(&report).contains("Type accuracy:        33.3% (1/3)"));
+        temp_dir.close()?;
+        Ok(())
+    }
+}
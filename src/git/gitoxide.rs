@@ -0,0 +1,505 @@
+//! In-process implementation of [`GitBackend`] using gitoxide. Diffs the
+//! index against `HEAD` without spawning `git`, and renders the result in the
+//! same format as `git diff --staged`. Finding the repository root and
+//! committing still go through the `git` CLI.
+
+use super::{
+    FileChange, FileKind, FileStatus, GitBackend, LFS_POINTER_MAX_BYTES, LFS_POINTER_PREFIX,
+    ModeChange, StagedChangesSummary, SubmoduleChange, decode_diff,
+};
+use anyhow::{Context, Result};
+use gix::ObjectId;
+use gix::bstr::{BString, ByteSlice};
+use gix::diff::blob::intern::InternedInput;
+use gix::diff::blob::unified_diff::{ConsumeHunk, ContextSize, DiffLineKind, HunkHeader};
+use gix::diff::blob::{Algorithm, UnifiedDiff, sources};
+use gix::diff::index::{Action, ChangeRef};
+use gix::status::tree_index::TrackRenames;
+use std::cell::OnceCell;
+use std::convert::Infallible;
+use std::io::Write;
+use std::path::Path;
+
+const MODE_GITLINK: u32 = 0o160000;
+const MODE_SYMLINK: u32 = 0o120000;
+const MODE_TYPE_MASK: u32 = 0o170000;
+/// Git treats a blob as binary if this prefix contains a NUL byte.
+const BINARY_SNIFF_BYTES: usize = 8000;
+
+pub struct GitoxideBackend {
+    repo: gix::ThreadSafeRepository,
+    /// The staged changes, loaded by the first query and shared by the rest.
+    staged: OnceCell<Vec<StagedEntry>>,
+}
+
+impl GitoxideBackend {
+    pub fn open(repo_path: &Path) -> Result<Self> {
+        let repo = gix::ThreadSafeRepository::discover(repo_path)
+            .with_context(|| format!("Failed to open git repository at {:?}", repo_path))?;
+        Ok(GitoxideBackend {
+            repo,
+            staged: OnceCell::new(),
+        })
+    }
+
+    fn staged_entries(&self) -> Result<&[StagedEntry]> {
+        if let Some(entries) = self.staged.get() {
+            return Ok(entries);
+        }
+        let entries = self.load_staged_entries()?;
+        Ok(self.staged.get_or_init(|| entries))
+    }
+
+    fn load_staged_entries(&self) -> Result<Vec<StagedEntry>> {
+        let repo = self.repo.to_thread_local();
+        let head_tree = repo
+            .head_tree_id_or_empty()
+            .context("Failed to resolve the HEAD tree")?
+            .detach();
+        let index = repo.index_or_empty().context("Failed to read the index")?;
+
+        let mut changes = Vec::new();
+        repo.tree_index_status(
+            &head_tree,
+            &index,
+            None,
+            TrackRenames::AsConfigured,
+            |change, _, _| {
+                changes.push(change.into_owned());
+                Ok::<_, Infallible>(Action::Continue)
+            },
+        )
+        .context("Failed to diff the index against HEAD")?;
+
+        let mut entries = Vec::with_capacity(changes.len());
+        for change in changes {
+            entries.push(StagedEntry::load(&repo, change)?);
+        }
+        entries.sort_by(|a, b| a.sort_path().cmp(b.sort_path()));
+        Ok(entries)
+    }
+}
+
+impl GitBackend for GitoxideBackend {
+    fn has_staged_files(&self) -> Result<bool> {
+        Ok(!self.staged_entries()?.is_empty())
+    }
+
+    fn staged_diff(&self) -> Result<String> {
+        let mut raw = Vec::new();
+        for entry in self.staged_entries()? {
+            entry.write_patch(&mut raw)?;
+        }
+        Ok(decode_diff(&raw))
+    }
+
+    fn staged_changes_summary(&self) -> Result<StagedChangesSummary> {
+        let mut files = Vec::new();
+        for entry in self.staged_entries()? {
+            let (added, removed) = if entry.is_binary() {
+                (None, None)
+            } else {
+                let (added, removed) = entry.line_counts()?;
+                (Some(added), Some(removed))
+            };
+            files.push(entry.file_change(added, removed));
+        }
+        Ok(StagedChangesSummary::from_files(files))
+    }
+
+    fn previous_commit_message(&self) -> Result<Option<String>> {
+        let repo = self.repo.to_thread_local();
+        if repo.head().context("Failed to read HEAD")?.is_unborn() {
+            return Ok(None);
+        }
+        let commit = repo
+            .head_commit()
+            .context("Failed to read the HEAD commit")?;
+        let message = commit.message_raw_sloppy().to_str_lossy();
+        Ok(Some(message.trim().to_string()))
+    }
+
+    fn current_branch(&self) -> Result<Option<String>> {
+        let repo = self.repo.to_thread_local();
+        let name = repo.head_name().context("Failed to read HEAD")?;
+        Ok(name.map(|name| name.shorten().to_str_lossy().into_owned()))
+    }
+}
+
+/// One side of a staged change, with its blob loaded.
+struct Side {
+    path: BString,
+    mode: u32,
+    id: ObjectId,
+    data: Vec<u8>,
+}
+
+impl Side {
+    fn load(
+        repo: &gix::Repository,
+        path: &[u8],
+        mode: gix::index::entry::Mode,
+        id: &gix::oid,
+    ) -> Result<Self> {
+        let mode = mode.bits();
+        let data = if mode == MODE_GITLINK {
+            // Submodule commits are not in this repository's object database;
+            // render them the way `git diff` does.
+            format!("Subproject commit {}\n", id).into_bytes()
+        } else {
+            repo.find_blob(id)
+                .with_context(|| format!("Failed to read blob {} for {}", id, path.as_bstr()))?
+                .take_data()
+        };
+        Ok(Side {
+            path: path.into(),
+            mode,
+            id: id.to_owned(),
+            data,
+        })
+    }
+
+    fn kind(&self) -> FileKind {
+        match self.mode {
+            MODE_GITLINK => FileKind::Submodule,
+            MODE_SYMLINK => FileKind::Symlink,
+            mode if mode & 0o111 != 0 => FileKind::Executable,
+            _ => FileKind::Regular,
+        }
+    }
+
+    fn is_binary(&self) -> bool {
+        self.mode != MODE_GITLINK
+            && self.data[..self.data.len().min(BINARY_SNIFF_BYTES)].contains(&0)
+    }
+
+    fn short_id(&self) -> String {
+        self.id.to_hex_with_len(7).to_string()
+    }
+}
+
+struct StagedEntry {
+    status: FileStatus,
+    old: Option<Side>,
+    new: Option<Side>,
+}
+
+impl StagedEntry {
+    fn load(repo: &gix::Repository, change: ChangeRef<'_, '_>) -> Result<Self> {
+        let entry = match change {
+            ChangeRef::Addition {
+                location,
+                entry_mode,
+                id,
+                ..
+            } => StagedEntry {
+                status: FileStatus::Added,
+                old: None,
+                new: Some(Side::load(repo, &location, entry_mode, &id)?),
+            },
+            ChangeRef::Deletion {
+                location,
+                entry_mode,
+                id,
+                ..
+            } => StagedEntry {
+                status: FileStatus::Deleted,
+                old: Some(Side::load(repo, &location, entry_mode, &id)?),
+                new: None,
+            },
+            ChangeRef::Modification {
+                location,
+                previous_entry_mode,
+                previous_id,
+                entry_mode,
+                id,
+                ..
+            } => {
+                let old = Side::load(repo, &location, previous_entry_mode, &previous_id)?;
+                let new = Side::load(repo, &location, entry_mode, &id)?;
+                // Exec-bit flips stay modifications; file <-> symlink <->
+                // submodule switches are type changes, as in `git status`.
+                let type_changed = old.mode & MODE_TYPE_MASK != new.mode & MODE_TYPE_MASK;
+                StagedEntry {
+                    status: if type_changed {
+                        FileStatus::TypeChanged
+                    } else {
+                        FileStatus::Modified
+                    },
+                    old: Some(old),
+                    new: Some(new),
+                }
+            }
+            ChangeRef::Rewrite {
+                source_location,
+                source_entry_mode,
+                source_id,
+                location,
+                entry_mode,
+                id,
+                copy,
+                ..
+            } => StagedEntry {
+                status: if copy {
+                    FileStatus::Copied
+                } else {
+                    FileStatus::Renamed
+                },
+                old: Some(Side::load(
+                    repo,
+                    &source_location,
+                    source_entry_mode,
+                    &source_id,
+                )?),
+                new: Some(Side::load(repo, &location, entry_mode, &id)?),
+            },
+        };
+        Ok(entry)
+    }
+
+    /// The side that exists after the change, or the deleted one.
+    fn live(&self) -> &Side {
+        self.new
+            .as_ref()
+            .or(self.old.as_ref())
+            .expect("a staged change has at least one side")
+    }
+
+    fn sort_path(&self) -> &[u8] {
+        self.live().path.as_slice()
+    }
+
+    fn is_binary(&self) -> bool {
+        self.old.iter().chain(&self.new).any(Side::is_binary)
+    }
+
+    fn old_data(&self) -> &[u8] {
+        self.old
+            .as_ref()
+            .map(|side| side.data.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn new_data(&self) -> &[u8] {
+        self.new
+            .as_ref()
+            .map(|side| side.data.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn line_counts(&self) -> Result<(u64, u64)> {
+        let (added, removed, _) = unified_diff(self.old_data(), self.new_data())?;
+        Ok((added, removed))
+    }
+
+    fn file_change(&self, added: Option<u64>, removed: Option<u64>) -> FileChange {
+        let live = self.live();
+        let both_sides = self.old.as_ref().zip(self.new.as_ref());
+        let mode_change = both_sides
+            .filter(|(old, new)| old.mode != new.mode)
+            .map(|(old, new)| ModeChange {
+                old: format!("{:06o}", old.mode),
+                new: format!("{:06o}", new.mode),
+            });
+        let gitlink_sha = |side: &Option<Side>| {
+            side.as_ref()
+                .filter(|s| s.mode == MODE_GITLINK)
+                .map(|s| s.id.to_string())
+        };
+        let submodule = (gitlink_sha(&self.old).is_some() || gitlink_sha(&self.new).is_some())
+            .then(|| SubmoduleChange {
+                old_sha: gitlink_sha(&self.old),
+                new_sha: gitlink_sha(&self.new),
+            });
+        let lfs_pointer = self.new.as_ref().is_some_and(|new| {
+            matches!(new.kind(), FileKind::Regular | FileKind::Executable)
+                && new.data.len() as u64 <= LFS_POINTER_MAX_BYTES
+                && new.data.starts_with(LFS_POINTER_PREFIX)
+        });
+        let renamed = matches!(self.status, FileStatus::Renamed | FileStatus::Copied);
+        FileChange {
+            status: self.status,
+            path: live.path.to_str_lossy().into_owned(),
+            old_path: self
+                .old
+                .as_ref()
+                .filter(|_| renamed)
+                .map(|old| old.path.to_str_lossy().into_owned()),
+            kind: live.kind(),
+            added,
+            removed,
+            mode_change,
+            submodule,
+            lfs_pointer,
+        }
+    }
+
+    /// Writes this change in `git diff` format.
+    fn write_patch(&self, out: &mut Vec<u8>) -> Result<()> {
+        let live = self.live();
+        let old_path = self.old.as_ref().map_or(&live.path, |old| &old.path);
+        out.extend_from_slice(b"diff --git a/");
+        out.extend_from_slice(old_path);
+        out.extend_from_slice(b" b/");
+        out.extend_from_slice(&live.path);
+        out.push(b'\n');
+
+        let null_id = "0000000";
+        match (&self.old, &self.new) {
+            (None, Some(new)) => {
+                writeln!(out, "new file mode {:06o}", new.mode)?;
+                writeln!(out, "index {}..{}", null_id, new.short_id())?;
+            }
+            (Some(old), None) => {
+                writeln!(out, "deleted file mode {:06o}", old.mode)?;
+                writeln!(out, "index {}..{}", old.short_id(), null_id)?;
+            }
+            (Some(old), Some(new)) => {
+                if old.mode != new.mode {
+                    writeln!(out, "old mode {:06o}", old.mode)?;
+                    writeln!(out, "new mode {:06o}", new.mode)?;
+                }
+                if let FileStatus::Renamed | FileStatus::Copied = self.status {
+                    let verb = if self.status == FileStatus::Renamed {
+                        "rename"
+                    } else {
+                        "copy"
+                    };
+                    writeln!(out, "similarity index {}%", self.similarity()?)?;
+                    out.extend_from_slice(format!("{} from ", verb).as_bytes());
+                    out.extend_from_slice(&old.path);
+                    out.extend_from_slice(format!("\n{} to ", verb).as_bytes());
+                    out.extend_from_slice(&new.path);
+                    out.push(b'\n');
+                }
+                if old.id != new.id {
+                    write!(out, "index {}..{}", old.short_id(), new.short_id())?;
+                    if old.mode == new.mode {
+                        write!(out, " {:06o}", new.mode)?;
+                    }
+                    out.push(b'\n');
+                }
+            }
+            (None, None) => {}
+        }
+
+        if self.old_data() == self.new_data() && self.old.is_some() && self.new.is_some() {
+            return Ok(());
+        }
+        let side_label = |side: &Option<Side>, prefix: &[u8]| -> Vec<u8> {
+            match side {
+                Some(side) => [prefix, side.path.as_slice()].concat(),
+                None => b"/dev/null".to_vec(),
+            }
+        };
+        let (a, b) = (side_label(&self.old, b"a/"), side_label(&self.new, b"b/"));
+        if self.is_binary() {
+            out.extend_from_slice(b"Binary files ");
+            out.extend_from_slice(&a);
+            out.extend_from_slice(b" and ");
+            out.extend_from_slice(&b);
+            out.extend_from_slice(b" differ\n");
+            return Ok(());
+        }
+        let (_, _, hunks) = unified_diff(self.old_data(), self.new_data())?;
+        if hunks.is_empty() {
+            return Ok(());
+        }
+        out.extend_from_slice(b"--- ");
+        out.extend_from_slice(&a);
+        out.extend_from_slice(b"\n+++ ");
+        out.extend_from_slice(&b);
+        out.push(b'\n');
+        out.extend_from_slice(&hunks);
+        Ok(())
+    }
+
+    /// Share of lines the two sides have in common, as a percentage. Git
+    /// measures bytes, so this is an approximation for edited renames.
+    fn similarity(&self) -> Result<u64> {
+        let (old, new) = (self.old_data(), self.new_data());
+        if old == new {
+            return Ok(100);
+        }
+        let (added, removed) = self.line_counts()?;
+        let old_lines = old.lines_with_terminator().count() as u64;
+        let new_lines = new.lines_with_terminator().count() as u64;
+        let total = old_lines.max(new_lines).max(1);
+        let common = (old_lines - removed.min(old_lines)).min(new_lines - added.min(new_lines));
+        Ok(common * 100 / total)
+    }
+}
+
+/// Renders the line diff of `old` and `new` as unified diff hunks with three
+/// lines of context, returning `(added, removed, hunks)`.
+fn unified_diff(old: &[u8], new: &[u8]) -> Result<(u64, u64, Vec<u8>)> {
+    let input = InternedInput::new(
+        sources::byte_lines_with_terminator(old),
+        sources::byte_lines_with_terminator(new),
+    );
+    let writer = HunkWriter::default();
+    let sink = UnifiedDiff::new(&input, writer, ContextSize::symmetrical(3));
+    let writer = gix::diff::blob::diff(Algorithm::Myers, &input, sink)
+        .context("Failed to render line diff")?;
+    Ok((writer.added, writer.removed, writer.out))
+}
+
+#[derive(Default)]
+struct HunkWriter {
+    out: Vec<u8>,
+    added: u64,
+    removed: u64,
+}
+
+impl HunkWriter {
+    /// `-start,len` as git prints it: the length is omitted when it is one,
+    /// and an empty range starts at the line before it.
+    fn range(start: u32, len: u32) -> String {
+        match len {
+            0 => format!("{},0", start.saturating_sub(1)),
+            1 => start.to_string(),
+            _ => format!("{},{}", start, len),
+        }
+    }
+}
+
+impl ConsumeHunk for HunkWriter {
+    type Out = HunkWriter;
+
+    fn consume_hunk(
+        &mut self,
+        header: HunkHeader,
+        lines: &[(DiffLineKind, &[u8])],
+    ) -> std::io::Result<()> {
+        writeln!(
+            self.out,
+            "@@ -{} +{} @@",
+            HunkWriter::range(header.before_hunk_start, header.before_hunk_len),
+            HunkWriter::range(header.after_hunk_start, header.after_hunk_len)
+        )?;
+        for (kind, line) in lines {
+            let prefix = match kind {
+                DiffLineKind::Context => b' ',
+                DiffLineKind::Add => {
+                    self.added += 1;
+                    b'+'
+                }
+                DiffLineKind::Remove => {
+                    self.removed += 1;
+                    b'-'
+                }
+            };
+            self.out.push(prefix);
+            self.out.extend_from_slice(line);
+            if !line.ends_with(b"\n") {
+                self.out
+                    .extend_from_slice(b"\n\\ No newline at end of file\n");
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Self::Out {
+        self
+    }
+}
@@ -0,0 +1,125 @@
+//! Lookup of identities in a repository's `.mailmap`.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+const MAILMAP_FILE_NAME: &str = ".mailmap";
+
+/// A canonical identity and the emails that map to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MailmapEntry {
+    name: String,
+    email: String,
+    aliases: Vec<String>,
+}
+
+impl MailmapEntry {
+    fn matches(&self, key: &str) -> bool {
+        let key = key.trim().to_lowercase();
+        let local_part = |email: &str| email.split('@').next().unwrap_or_default().to_lowercase();
+        self.name.to_lowercase() == key
+            || std::iter::once(&self.email)
+                .chain(&self.aliases)
+                .any(|email| email.to_lowercase() == key || local_part(email) == key)
+    }
+
+    fn identity(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
+    }
+}
+
+/// Parses the `Proper Name <proper@email> [Commit Name] [<commit@email>]`
+/// lines of a mailmap. Lines without a proper name are skipped, since they
+/// cannot produce a full identity.
+fn parse(contents: &str) -> Vec<MailmapEntry> {
+    let mut entries: Vec<MailmapEntry> = Vec::new();
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let Some((name, rest)) = line.split_once('<') else {
+            continue;
+        };
+        let Some((email, rest)) = rest.split_once('>') else {
+            continue;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        let alias = rest
+            .split_once('<')
+            .and_then(|(_, alias)| alias.split_once('>'))
+            .map(|(alias, _)| alias.trim().to_string());
+        let email = email.trim().to_string();
+        match entries
+            .iter_mut()
+            .find(|entry| entry.name == name && entry.email == email)
+        {
+            Some(entry) => entry.aliases.extend(alias),
+            None => entries.push(MailmapEntry {
+                name: name.to_string(),
+                email,
+                aliases: alias.into_iter().collect(),
+            }),
+        }
+    }
+    entries
+}
+
+/// Finds `Name <email>` for a name, email or email user name listed in the
+/// repository's `.mailmap`.
+pub fn lookup(repo_path: &Path, key: &str) -> Result<Option<String>> {
+    let path = repo_path.join(MAILMAP_FILE_NAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(parse(&contents)
+        .iter()
+        .find(|entry| entry.matches(key))
+        .map(MailmapEntry::identity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const MAILMAP: &str = "\
+# Canonical identities
+Alice Smith <alice@example.com> <asmith@old-corp.example>
+Alice Smith <alice@example.com> Alice <alice@laptop.local>
+<bob@example.com> <bob@old.example>
+";
+
+    #[test]
+    fn test_parse_merges_aliases() {
+        assert_eq!(
+            parse(MAILMAP),
+            vec![MailmapEntry {
+                name: "Alice Smith".to_string(),
+                email: "alice@example.com".to_string(),
+                aliases: vec![
+                    "asmith@old-corp.example".to_string(),
+                    "alice@laptop.local".to_string()
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_lookup_by_name_email_or_user() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        assert_eq!(lookup(temp_dir.path(), "alice")?, None);
+        fs::write(temp_dir.path().join(MAILMAP_FILE_NAME), MAILMAP)?;
+
+        let alice = Some("Alice Smith <alice@example.com>".to_string());
+        assert_eq!(lookup(temp_dir.path(), "alice smith")?, alice);
+        assert_eq!(lookup(temp_dir.path(), "asmith@old-corp.example")?, alice);
+        assert_eq!(lookup(temp_dir.path(), "alice")?, alice);
+        assert_eq!(lookup(temp_dir.path(), "bob")?, None);
+        temp_dir.close()?;
+        Ok(())
+    }
+}
@@ -1,34 +1,107 @@
+#[cfg(feature = "gix")]
+mod gitoxide;
+mod mailmap;
+
+use crate::config::{CommitConfig, GitBackendKind, GitConfig};
 use anyhow::{Context, Result, bail};
+use serde::Serialize;
 use std::collections::HashMap;
-use std::path::Path;
-use std::process::{Command, Output, Stdio};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
 use std::str;
 
+/// Read-only queries about the staged changes. Commits always go through the
+/// git CLI so hooks, signing and user config apply exactly as for `git commit`.
+pub trait GitBackend {
+    fn has_staged_files(&self) -> Result<bool>;
+    fn staged_diff(&self) -> Result<String>;
+    fn staged_changes_summary(&self) -> Result<StagedChangesSummary>;
+    fn previous_commit_message(&self) -> Result<Option<String>>;
+    fn current_branch(&self) -> Result<Option<String>>;
+}
+
+/// Shells out to `git` for every query.
+pub struct CliBackend {
+    repo_path: PathBuf,
+}
+
+impl CliBackend {
+    pub fn new(repo_path: &Path) -> Self {
+        CliBackend {
+            repo_path: repo_path.to_path_buf(),
+        }
+    }
+}
+
+impl GitBackend for CliBackend {
+    fn has_staged_files(&self) -> Result<bool> {
+        has_staged_files(&self.repo_path)
+    }
+
+    fn staged_diff(&self) -> Result<String> {
+        get_staged_diff(&self.repo_path)
+    }
+
+    fn staged_changes_summary(&self) -> Result<StagedChangesSummary> {
+        get_staged_changes_summary(&self.repo_path)
+    }
+
+    fn previous_commit_message(&self) -> Result<Option<String>> {
+        get_previous_commit_message(&self.repo_path)
+    }
+
+    fn current_branch(&self) -> Result<Option<String>> {
+        get_current_branch(&self.repo_path)
+    }
+}
+
+/// Opens the backend selected in `[git] backend`.
+pub fn open_backend(config: &GitConfig, repo_path: &Path) -> Result<Box<dyn GitBackend>> {
+    match config.backend {
+        GitBackendKind::Cli => Ok(Box::new(CliBackend::new(repo_path))),
+        #[cfg(feature = "gix")]
+        GitBackendKind::Gix => Ok(Box::new(gitoxide::GitoxideBackend::open(repo_path)?)),
+        #[cfg(not(feature = "gix"))]
+        GitBackendKind::Gix => bail!(
+            "The gix git backend is not available in this build. Rebuild with `--features gix` or set `[git] backend = \"cli\"`."
+        ),
+    }
+}
+
+/// A git command in `repo_path`. Path quoting is turned off so non-ASCII
+/// names come through as their raw bytes instead of octal escapes.
+fn git_command(repo_path: &Path) -> Command {
+    let mut command = Command::new("git");
+    command
+        .current_dir(repo_path)
+        .args(["-c", "core.quotepath=off"]);
+    command
+}
+
 fn execute_git_command(repo_path: &Path, args: &[&str]) -> Result<Output, anyhow::Error> {
+    execute_git_command_with_input(repo_path, args, None)
+}
+
+/// Like `execute_git_command`, but feeds `input` to the command's stdin.
+fn execute_git_command_with_input(
+    repo_path: &Path,
+    args: &[&str],
+    input: Option<&[u8]>,
+) -> Result<Output, anyhow::Error> {
     let command_str = format!("git {}", args.join(" "));
-    let output = Command::new("git")
-        .current_dir(repo_path)
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .with_context(|| {
-            format!(
-                "Failed to execute git command: '{}' in {:?}. Ensure 'git' is installed and in your PATH.",
-                command_str, repo_path
-            )
-        })?;
+    let output = spawn_git_command(repo_path, args, input)?;
 
     if !output.status.success() {
-        let stderr_str = str::from_utf8(&output.stderr)
-            .unwrap_or("[non-utf8 stderr]")
-            .trim();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr_str = stderr.trim();
         if stderr_str.contains("fatal: not a git repository") {
             bail!("Not a git repository.");
         }
-        let stdout_str = str::from_utf8(&output.stdout)
-            .unwrap_or("[non-utf8 stdout]")
-            .trim();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stdout_str = stdout.trim();
         bail!(
             "Git command '{}' failed in {:?} with status {}:\nStdout: {}\nStderr: {}",
             command_str,
@@ -41,25 +114,145 @@ fn execute_git_command(repo_path: &Path, args: &[&str]) -> Result<Output, anyhow
     Ok(output)
 }
 
+/// Runs git to completion, feeding `input` on stdin, without checking the
+/// exit status.
+fn spawn_git_command(repo_path: &Path, args: &[&str], input: Option<&[u8]>) -> Result<Output> {
+    let spawn_and_wait = || -> std::io::Result<Output> {
+        let mut child = git_command(repo_path)
+            .args(args)
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input)?;
+        }
+        child.wait_with_output()
+    };
+    spawn_and_wait().with_context(|| {
+        format!(
+            "Failed to execute git command: 'git {}' in {:?}. Ensure 'git' is installed and in your PATH.",
+            args.join(" "),
+            repo_path
+        )
+    })
+}
+
+/// The top level of the work tree containing `dir`. Honours `GIT_DIR` and
+/// `GIT_WORK_TREE`, and returns the linked worktree's own root when `dir` is
+/// inside one.
+pub fn resolve_repo_root(dir: &Path) -> Result<PathBuf> {
+    let output = git_command(dir)
+        .args(["rev-parse", "--show-toplevel"])
+        // The failures are told apart by their message, so keep it untranslated.
+        .env("LC_ALL", "C")
+        .output()
+        .with_context(|| {
+            format!(
+                "Failed to execute git in {:?}. Ensure 'git' is installed and in your PATH.",
+                dir
+            )
+        })?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("not a git repository") {
+        bail!(
+            "Not a git repository: {}. Run ai-commit inside a repository or pass -C <path>.",
+            dir.display()
+        );
+    }
+    if stderr.contains("must be run in a work tree") {
+        bail!(
+            "{} is not inside a work tree (bare repository or .git directory). ai-commit needs a checked-out work tree.",
+            dir.display()
+        );
+    }
+    if !output.status.success() {
+        bail!(
+            "Failed to find the repository root of {}: {}",
+            dir.display(),
+            stderr.trim()
+        );
+    }
+    let root = String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches('\n')
+        .to_string();
+    if root.is_empty() {
+        bail!(
+            "{} is not inside a work tree. Set GIT_WORK_TREE when using GIT_DIR.",
+            dir.display()
+        );
+    }
+    Ok(PathBuf::from(root))
+}
+
 pub fn has_staged_files(repo_path: &Path) -> Result<bool, anyhow::Error> {
     let output = execute_git_command(
         repo_path,
         &["status", "--porcelain", "--untracked-files=no"],
     )
     .context("Failed to get git status to check for staged files")?;
-    let stdout_str = str::from_utf8(&output.stdout)
-        .context("Failed to read git status output as UTF-8")?
-        .trim();
-    Ok(!stdout_str.is_empty())
+    Ok(!output.stdout.trim_ascii().is_empty())
 }
 
 pub fn get_staged_diff(repo_path: &Path) -> Result<String, anyhow::Error> {
     let diff_output = execute_git_command(repo_path, &["diff", "--staged"])
         .context("Failed to get staged git diff")?;
-    let diff_stdout = str::from_utf8(&diff_output.stdout)
-        .context("Failed to read git diff output as UTF-8")?
-        .to_string();
-    Ok(diff_stdout)
+    Ok(decode_diff(&diff_output.stdout))
+}
+
+/// Decodes `git diff` output file by file. Paths are decoded lossily; a file
+/// whose content is not valid UTF-8 keeps its headers but has its hunks
+/// replaced by a one-line summary, so one Latin-1 file cannot garble or abort
+/// the whole prompt.
+fn decode_diff(raw: &[u8]) -> String {
+    let mut decoded = String::with_capacity(raw.len());
+    for section in split_diff_sections(raw) {
+        let hunks_start = section
+            .split_inclusive(|&b| b == b'\n')
+            .take_while(|line| !line.starts_with(b"@@"))
+            .map(<[u8]>::len)
+            .sum();
+        let (headers, hunks) = section.split_at(hunks_start);
+        decoded.push_str(&String::from_utf8_lossy(headers));
+        if let Ok(text) = str::from_utf8(hunks) {
+            decoded.push_str(text);
+            continue;
+        }
+        let (mut added, mut removed) = (0, 0);
+        for line in hunks.split_inclusive(|&b| b == b'\n') {
+            if line.starts_with(b"+") {
+                added += 1;
+            } else if line.starts_with(b"-") {
+                removed += 1;
+            }
+        }
+        decoded.push_str(&format!(
+            "[Content is not valid UTF-8: {} added and {} removed lines not shown]\n",
+            added, removed
+        ));
+    }
+    decoded
+}
+
+/// Splits diff output at each `diff --git` header line.
+fn split_diff_sections(raw: &[u8]) -> Vec<&[u8]> {
+    const HEADER: &[u8] = b"diff --git ";
+    let mut starts: Vec<usize> = (0..raw.len())
+        .filter(|&i| (i == 0 || raw[i - 1] == b'\n') && raw[i..].starts_with(HEADER))
+        .collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    starts
+        .iter()
+        .zip(starts.iter().skip(1).chain(std::iter::once(&raw.len())))
+        .map(|(&start, &end)| &raw[start..end])
+        .filter(|section| !section.is_empty())
+        .collect()
 }
 
 fn execute_git_command_for_summary_bytes(
@@ -67,8 +260,7 @@ fn execute_git_command_for_summary_bytes(
     args: &[&str],
 ) -> Result<Vec<u8>, anyhow::Error> {
     let command_str = format!("git {}", args.join(" "));
-    let output = Command::new("git")
-        .current_dir(repo_path)
+    let output = git_command(repo_path)
         .args(args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -81,9 +273,8 @@ fn execute_git_command_for_summary_bytes(
         })?;
 
     if !output.status.success() {
-        let stderr_str = str::from_utf8(&output.stderr)
-            .unwrap_or("[non-utf8 stderr summary]")
-            .trim();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr_str = stderr.trim();
         if stderr_str.contains("fatal: not a git repository") {
             bail!("Not a git repository.");
         }
@@ -102,270 +293,524 @@ fn execute_git_command_for_summary_bytes(
 pub struct StagedChangesSummary {
     pub binary_file_changes: Vec<String>,
     pub structure_changes: Vec<String>,
+    /// One entry per staged path, in `git diff --raw` order.
+    pub files: Vec<FileChange>,
+}
+
+impl StagedChangesSummary {
+    /// Builds the summary, including the binary and structure descriptions,
+    /// from per-file entries alone.
+    pub fn from_files(files: Vec<FileChange>) -> Self {
+        let mut summary = StagedChangesSummary::default();
+        for file in &files {
+            let path = &file.path;
+            let binary = file.added.is_none() && file.kind != FileKind::Submodule;
+            let from = file.old_path.as_deref().unwrap_or_default();
+            let (structure, binary_desc) = match file.status {
+                FileStatus::Added => (None, format!("added binary file: {}", path)),
+                FileStatus::Modified => (None, format!("modified binary file: {}", path)),
+                FileStatus::Deleted => (Some(format!("deleted file: {}", path)), String::new()),
+                FileStatus::Renamed => (
+                    Some(format!("renamed: {} to {}", from, path)),
+                    format!("renamed binary file: {} to {}", from, path),
+                ),
+                FileStatus::Copied => (
+                    Some(format!("copied: {} to {}", from, path)),
+                    format!("copied binary file to: {}", path),
+                ),
+                FileStatus::TypeChanged => (
+                    Some(format!("type changed for: {}", path)),
+                    format!("type changed to binary: {}", path),
+                ),
+            };
+            summary.structure_changes.extend(structure);
+            if binary && file.status != FileStatus::Deleted {
+                summary.binary_file_changes.push(binary_desc);
+            }
+        }
+        summary.binary_file_changes.sort();
+        summary.structure_changes.sort();
+        summary.files = files;
+        summary
+    }
 }
 
-fn get_binary_status_map(repo_path: &Path) -> Result<HashMap<String, bool>> {
-    let numstat_output_bytes =
-        execute_git_command_for_summary_bytes(repo_path, &["diff", "--staged", "--numstat", "-z"])?;
-    let mut binary_map = HashMap::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+    Copied,
+    TypeChanged,
+}
 
-    if numstat_output_bytes.is_empty() || numstat_output_bytes.iter().all(|&b| b == 0) {
-        return Ok(binary_map);
+impl FileStatus {
+    fn from_raw(status: &str) -> Option<Self> {
+        match status.chars().next()? {
+            'A' => Some(FileStatus::Added),
+            'M' => Some(FileStatus::Modified),
+            'D' => Some(FileStatus::Deleted),
+            'R' => Some(FileStatus::Renamed),
+            'C' => Some(FileStatus::Copied),
+            'T' => Some(FileStatus::TypeChanged),
+            _ => None,
+        }
     }
 
-    let mut fields_iter = numstat_output_bytes
-        .split(|&b| b == 0)
-        .filter(|s| !s.is_empty());
+    /// The single-letter code git uses for this status.
+    pub fn code(self) -> char {
+        match self {
+            FileStatus::Added => 'A',
+            FileStatus::Modified => 'M',
+            FileStatus::Deleted => 'D',
+            FileStatus::Renamed => 'R',
+            FileStatus::Copied => 'C',
+            FileStatus::TypeChanged => 'T',
+        }
+    }
+}
 
-    while let Some(first_segment_bytes) = fields_iter.next() {
-        let first_segment_str = str::from_utf8(first_segment_bytes).with_context(|| {
-            format!(
-                "Non-UTF8 first_segment in numstat: {:?}",
-                first_segment_bytes
-            )
-        })?;
-        let parts: Vec<&str> = first_segment_str.split('\t').collect();
-
-        if parts.len() == 3 {
-            let added_str = parts[0];
-            let deleted_str = parts[1];
-            let third_part_str = parts[2];
-            let is_binary_stats = added_str == "-" && deleted_str == "-";
-
-            if third_part_str.is_empty() {
-                let _old_path_bytes = fields_iter.next().with_context(|| {
-                    format!(
-                        "Expected old_path after empty third part in numstat for segment: '{}'",
-                        first_segment_str
-                    )
-                })?;
-                let new_path_bytes = fields_iter.next().with_context(|| {
-                    format!(
-                        "Expected new_path after old_path (empty third part) for segment: '{}'",
-                        first_segment_str
-                    )
-                })?;
-                let new_path_str = str::from_utf8(new_path_bytes).with_context(|| {
-                    format!(
-                        "Non-UTF8 new_path (empty third part numstat): {:?}",
-                        new_path_bytes
-                    )
-                })?;
-                binary_map.insert(new_path_str.to_string(), is_binary_stats);
-            } else if third_part_str.ends_with('%')
-                && third_part_str.len() > 1
-                && third_part_str[..third_part_str.len() - 1]
-                    .parse::<u32>()
-                    .is_ok()
-            {
-                let _old_path_bytes = fields_iter.next().with_context(|| {
-                    format!(
-                        "Expected old_path after similarity score in numstat for segment: '{}'",
-                        first_segment_str
-                    )
-                })?;
-                let new_path_bytes = fields_iter.next().with_context(|| {
-                    format!(
-                        "Expected new_path after old_path (similarity score) for segment: '{}'",
-                        first_segment_str
-                    )
-                })?;
-                let new_path_str = str::from_utf8(new_path_bytes).with_context(|| {
-                    format!(
-                        "Non-UTF8 new_path (similarity score numstat): {:?}",
-                        new_path_bytes
-                    )
-                })?;
-                binary_map.insert(new_path_str.to_string(), is_binary_stats);
-            } else {
-                let path_str = third_part_str;
-                binary_map.insert(path_str.to_string(), is_binary_stats);
-            }
-        } else if parts.len() == 2 {
-            let added_str = parts[0];
-            let deleted_str = parts[1];
-            let is_binary_stats = added_str == "-" && deleted_str == "-";
-            let _old_path_bytes = fields_iter.next().with_context(|| {
-                format!(
-                    "Expected old_path (2-part numstat) for segment: '{}'",
-                    first_segment_str
-                )
-            })?;
-            let new_path_bytes = fields_iter.next().with_context(|| {
-                format!(
-                    "Expected new_path after old_path (2-part numstat) for segment: '{}'",
-                    first_segment_str
-                )
-            })?;
-            let new_path_str = str::from_utf8(new_path_bytes).with_context(|| {
-                format!("Non-UTF8 new_path (2-part numstat): {:?}", new_path_bytes)
-            })?;
-            binary_map.insert(new_path_str.to_string(), is_binary_stats);
-        } else {
+/// What kind of object a path is, derived from its git file mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    Regular,
+    Executable,
+    Symlink,
+    Submodule,
+}
+
+impl FileKind {
+    fn from_mode(mode: &str) -> Option<Self> {
+        match mode {
+            "100644" | "100664" => Some(FileKind::Regular),
+            "100755" => Some(FileKind::Executable),
+            "120000" => Some(FileKind::Symlink),
+            "160000" => Some(FileKind::Submodule),
+            _ => None,
         }
     }
-    Ok(binary_map)
 }
 
-pub fn get_staged_changes_summary(repo_path: &Path) -> Result<StagedChangesSummary> {
-    let mut summary = StagedChangesSummary::default();
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModeChange {
+    pub old: String,
+    pub new: String,
+}
+
+/// Commits a submodule pointer moved between; `None` when it was added or
+/// removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubmoduleChange {
+    pub old_sha: Option<String>,
+    pub new_sha: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileChange {
+    pub status: FileStatus,
+    pub path: String,
+    /// Source path of a rename or copy.
+    pub old_path: Option<String>,
+    pub kind: FileKind,
+    /// Line counts from `--numstat`; `None` for binary files.
+    pub added: Option<u64>,
+    pub removed: Option<u64>,
+    /// Set when both sides exist and their modes differ.
+    pub mode_change: Option<ModeChange>,
+    pub submodule: Option<SubmoduleChange>,
+    /// The staged content is a Git LFS pointer rather than the file itself.
+    pub lfs_pointer: bool,
+}
+
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+const LFS_POINTER_PREFIX: &[u8] = b"version https://git-lfs.github.com/spec/v1\n";
+/// LFS pointers are a few short lines; anything larger cannot be one.
+const LFS_POINTER_MAX_BYTES: u64 = 1024;
 
-    let status_check_output_bytes = execute_git_command_for_summary_bytes(
+/// Added and removed line counts; `None` when git reports `-` for binaries.
+type LineCounts = (Option<u64>, Option<u64>);
+
+/// Line counts from `git diff --staged --numstat -z -M`, keyed by new path.
+fn get_numstat_map(repo_path: &Path) -> Result<HashMap<String, LineCounts>> {
+    let output = execute_git_command_for_summary_bytes(
         repo_path,
-        &["status", "--porcelain=v1", "-z", "--untracked-files=no"],
+        &["diff", "--staged", "--numstat", "-z", "-M"],
     )?;
+    let mut map = HashMap::new();
+    let mut fields = output.split(|&b| b == 0);
+    while let Some(field) = fields.next() {
+        if field.is_empty() {
+            continue;
+        }
+        let field = String::from_utf8_lossy(field);
+        let mut parts = field.splitn(3, '\t');
+        let added = parts.next().and_then(|n| n.parse().ok());
+        let removed = parts.next().and_then(|n| n.parse().ok());
+        let path = match parts.next() {
+            Some(path) if !path.is_empty() => path.to_string(),
+            // Renames and copies: an empty path followed by old and new paths.
+            _ => {
+                fields.next();
+                match fields.next() {
+                    Some(new_path) => String::from_utf8_lossy(new_path).into_owned(),
+                    None => break,
+                }
+            }
+        };
+        map.insert(path, (added, removed));
+    }
+    Ok(map)
+}
 
-    if status_check_output_bytes.is_empty() || status_check_output_bytes.iter().all(|&x| x == 0) {
-        return Ok(summary);
+/// Parses `git diff --raw -z --no-abbrev` output into file changes without
+/// line counts, each paired with its new blob sha. `lfs_pointer` is set on
+/// every file that could be one.
+fn parse_raw_diff(output: &[u8]) -> Vec<(FileChange, String)> {
+    let mut files = Vec::new();
+    let mut fields = output.split(|&b| b == 0).filter(|f| !f.is_empty());
+    while let Some(meta) = fields.next() {
+        let meta = String::from_utf8_lossy(meta);
+        let Some(meta) = meta.strip_prefix(':') else {
+            continue;
+        };
+        let parts: Vec<&str> = meta.split(' ').collect();
+        let [old_mode, new_mode, old_sha, new_sha, status] = parts[..] else {
+            continue;
+        };
+        let Some(status) = FileStatus::from_raw(status) else {
+            fields.next();
+            continue;
+        };
+        let Some(first_path) = fields.next() else {
+            break;
+        };
+        let first_path = String::from_utf8_lossy(first_path).into_owned();
+        let (path, old_path) = match status {
+            FileStatus::Renamed | FileStatus::Copied => match fields.next() {
+                Some(new_path) => (
+                    String::from_utf8_lossy(new_path).into_owned(),
+                    Some(first_path),
+                ),
+                None => break,
+            },
+            _ => (first_path, None),
+        };
+
+        let live_mode = if status == FileStatus::Deleted {
+            old_mode
+        } else {
+            new_mode
+        };
+        let kind = FileKind::from_mode(live_mode).unwrap_or(FileKind::Regular);
+        let both_sides = old_mode != "000000" && new_mode != "000000";
+        let mode_change = (both_sides && old_mode != new_mode).then(|| ModeChange {
+            old: old_mode.to_string(),
+            new: new_mode.to_string(),
+        });
+        let is_gitlink = |mode: &str| mode == "160000";
+        let submodule = (is_gitlink(old_mode) || is_gitlink(new_mode)).then(|| {
+            let side = |mode: &str, sha: &str| {
+                (is_gitlink(mode) && sha != NULL_SHA).then(|| sha.to_string())
+            };
+            SubmoduleChange {
+                old_sha: side(old_mode, old_sha),
+                new_sha: side(new_mode, new_sha),
+            }
+        });
+
+        let change = FileChange {
+            status,
+            path,
+            old_path,
+            kind,
+            added: None,
+            removed: None,
+            mode_change,
+            submodule,
+            lfs_pointer: status != FileStatus::Deleted
+                && matches!(kind, FileKind::Regular | FileKind::Executable)
+                && new_sha != NULL_SHA,
+        };
+        files.push((change, new_sha.to_string()));
     }
+    files
+}
 
-    let binary_map = get_binary_status_map(repo_path)
-        .context("Failed to get binary status map for staged files")?;
+/// Returns the shas whose blobs are Git LFS pointers. Uses one
+/// `cat-file --batch-check` to find small blobs and one `--batch` to read them.
+fn find_lfs_pointers(repo_path: &Path, shas: &[&str]) -> Result<Vec<String>> {
+    if shas.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sizes = cat_file_batch(repo_path, "--batch-check", shas)?;
+    let small: Vec<&str> = String::from_utf8_lossy(&sizes)
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split(' ');
+            let sha = parts.next()?;
+            let size: u64 = parts.nth(1)?.parse().ok()?;
+            (size <= LFS_POINTER_MAX_BYTES).then_some(sha)
+        })
+        .filter_map(|sha| shas.iter().find(|s| **s == sha).copied())
+        .collect();
+    if small.is_empty() {
+        return Ok(Vec::new());
+    }
 
-    let mut status_fields_iter = status_check_output_bytes
-        .split(|&b| b == 0)
-        .filter(|s| !s.is_empty());
+    let contents = cat_file_batch(repo_path, "--batch", &small)?;
+    let mut pointers = Vec::new();
+    let mut rest = contents.as_slice();
+    while let Some(header_end) = rest.iter().position(|&b| b == b'\n') {
+        let header = String::from_utf8_lossy(&rest[..header_end]).into_owned();
+        let mut parts = header.split(' ');
+        let sha = parts.next().unwrap_or_default();
+        let Some(size) = parts.nth(1).and_then(|s| s.parse::<usize>().ok()) else {
+            break;
+        };
+        let body_start = header_end + 1;
+        let body_end = (body_start + size).min(rest.len());
+        if rest[body_start..body_end].starts_with(LFS_POINTER_PREFIX) {
+            pointers.push(sha.to_string());
+        }
+        // Each object is followed by a newline.
+        rest = rest.get(body_end + 1..).unwrap_or_default();
+    }
+    Ok(pointers)
+}
 
-    while let Some(entry_lead_bytes) = status_fields_iter.next() {
-        let entry_lead_str = str::from_utf8(entry_lead_bytes).with_context(|| {
-            format!(
-                "Failed to parse status entry lead: {:?}",
-                String::from_utf8_lossy(entry_lead_bytes)
-            )
-        })?;
+fn cat_file_batch(repo_path: &Path, mode: &str, shas: &[&str]) -> Result<Vec<u8>> {
+    let mut child = git_command(repo_path)
+        .args(["cat-file", mode])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute git cat-file {} in {:?}", mode, repo_path))?;
+    let input: String = shas.iter().map(|sha| format!("{}\n", sha)).collect();
+    let mut stdin = child
+        .stdin
+        .take()
+        .context("Failed to open git cat-file stdin")?;
+    // Write from a separate thread so a full stdout pipe cannot deadlock us.
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child
+        .wait_with_output()
+        .context("Failed to read git cat-file output")?;
+    writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("git cat-file writer thread panicked"))?
+        .context("Failed to write to git cat-file")?;
+    if !output.status.success() {
+        bail!(
+            "git cat-file {} failed in {:?}: {}",
+            mode,
+            repo_path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
 
-        if entry_lead_str.len() < 3 {
-            continue;
+/// Collects a typed entry for every staged path: status, paths, line counts,
+/// mode changes, submodule pointers and LFS pointers.
+pub fn get_staged_file_changes(repo_path: &Path) -> Result<Vec<FileChange>> {
+    let raw = execute_git_command_for_summary_bytes(
+        repo_path,
+        &["diff", "--staged", "--raw", "-z", "-M", "--no-abbrev"],
+    )?;
+    let entries = parse_raw_diff(&raw);
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let candidates: Vec<&str> = entries
+        .iter()
+        .filter(|(file, _)| file.lfs_pointer)
+        .map(|(_, sha)| sha.as_str())
+        .collect();
+    let pointers = find_lfs_pointers(repo_path, &candidates)
+        .context("Failed to check staged files for LFS pointers")?;
+    let numstat = get_numstat_map(repo_path).context("Failed to get staged line counts")?;
+
+    let mut files = Vec::with_capacity(entries.len());
+    for (mut file, sha) in entries {
+        if let Some(&(added, removed)) = numstat.get(&file.path) {
+            file.added = added;
+            file.removed = removed;
         }
+        file.lfs_pointer = file.lfs_pointer && pointers.contains(&sha);
+        files.push(file);
+    }
+    Ok(files)
+}
 
-        let status_codes = &entry_lead_str[0..2];
-        let path_part1_str = &entry_lead_str[3..];
-
-        let (current_path_for_processing, old_path_opt_string) =
-            if status_codes.starts_with('R') || status_codes.starts_with('C') {
-                if let Some(old_path_bytes) = status_fields_iter.next() {
-                    let old_path_str = str::from_utf8(old_path_bytes).with_context(|| {
-                        format!(
-                            "Failed to parse old_path for {} status: {:?}",
-                            status_codes,
-                            String::from_utf8_lossy(old_path_bytes)
-                        )
-                    })?;
-                    (path_part1_str, Some(old_path_str.to_string()))
-                } else {
-                    (path_part1_str, None)
-                }
-            } else {
-                (path_part1_str, None)
-            };
+/// Summarizes the staged changes from their per-file entries.
+pub fn get_staged_changes_summary(repo_path: &Path) -> Result<StagedChangesSummary> {
+    let files = get_staged_file_changes(repo_path)?;
+    Ok(StagedChangesSummary::from_files(files))
+}
 
-        let idx_status = status_codes.chars().next().unwrap_or(' ');
-
-        match idx_status {
-            'A' => {
-                let is_binary_file = binary_map
-                    .get(current_path_for_processing)
-                    .copied()
-                    .unwrap_or(false);
-                if is_binary_file {
-                    let change_desc = format!("added binary file: {}", current_path_for_processing);
-                    summary.binary_file_changes.push(change_desc);
-                }
-            }
-            'D' => {
-                let change_desc = format!("deleted file: {}", current_path_for_processing);
-                summary.structure_changes.push(change_desc);
-            }
-            'R' => {
-                if let Some(old_path) = old_path_opt_string {
-                    if !old_path.is_empty() && !current_path_for_processing.is_empty() {
-                        let struct_change_desc =
-                            format!("renamed: {} to {}", old_path, current_path_for_processing);
-                        summary.structure_changes.push(struct_change_desc);
-
-                        let is_binary_file = binary_map
-                            .get(current_path_for_processing)
-                            .copied()
-                            .unwrap_or(false);
-                        if is_binary_file {
-                            let bin_change_desc = format!(
-                                "renamed binary file: {} to {}",
-                                old_path, current_path_for_processing
-                            );
-                            summary.binary_file_changes.push(bin_change_desc);
-                        }
-                    }
-                }
-            }
-            'M' => {
-                let is_binary_file = binary_map
-                    .get(current_path_for_processing)
-                    .copied()
-                    .unwrap_or(false);
-                if is_binary_file {
-                    let change_desc =
-                        format!("modified binary file: {}", current_path_for_processing);
-                    summary.binary_file_changes.push(change_desc);
-                }
-            }
-            'T' => {
-                let struct_change_desc =
-                    format!("type changed for: {}", current_path_for_processing);
-                summary.structure_changes.push(struct_change_desc);
-                let is_binary_file = binary_map
-                    .get(current_path_for_processing)
-                    .copied()
-                    .unwrap_or(false);
-                if is_binary_file {
-                    let bin_change_desc =
-                        format!("type changed to binary: {}", current_path_for_processing);
-                    summary.binary_file_changes.push(bin_change_desc);
+/// Options for `git commit` beyond the message itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommitOptions {
+    pub sign: bool,
+    pub signoff: bool,
+    pub no_verify: bool,
+    pub author: Option<String>,
+    pub date: Option<String>,
+    /// Trailers as `Key: value`, including resolved `Co-authored-by` lines.
+    pub trailers: Vec<String>,
+}
+
+impl CommitOptions {
+    /// Validates the configured trailers and resolves every co-author to a
+    /// `Co-authored-by` trailer.
+    pub fn from_config(config: &CommitConfig, repo_path: &Path) -> Result<Self> {
+        let mut trailers = Vec::new();
+        for trailer in &config.trailers {
+            match trailer.split_once(':') {
+                Some((key, value)) if !key.trim().is_empty() && !value.trim().is_empty() => {
+                    trailers.push(format!("{}: {}", key.trim(), value.trim()))
                 }
+                _ => bail!("Invalid trailer '{}': expected 'Key: value'.", trailer),
             }
-            _ => {
-                if status_codes.starts_with('C') {
-                    if let Some(old_path) = old_path_opt_string {
-                        let struct_change_desc =
-                            format!("copied: {} to {}", old_path, current_path_for_processing);
-                        summary.structure_changes.push(struct_change_desc);
-                    }
-                    let is_binary_file = binary_map
-                        .get(current_path_for_processing)
-                        .copied()
-                        .unwrap_or(false);
-                    if is_binary_file {
-                        let change_desc =
-                            format!("copied binary file to: {}", current_path_for_processing);
-                        summary.binary_file_changes.push(change_desc);
-                    }
-                }
+        }
+        for co_author in &config.co_authors {
+            let trailer = format!(
+                "Co-authored-by: {}",
+                resolve_co_author(config, repo_path, co_author)?
+            );
+            if !trailers.contains(&trailer) {
+                trailers.push(trailer);
             }
         }
+        Ok(CommitOptions {
+            sign: config.sign,
+            signoff: config.signoff,
+            no_verify: config.no_verify,
+            author: config.author.clone(),
+            date: config.date.clone(),
+            trailers,
+        })
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.sign {
+            args.push("--gpg-sign".to_string());
+        }
+        if self.signoff {
+            args.push("--signoff".to_string());
+        }
+        if self.no_verify {
+            args.push("--no-verify".to_string());
+        }
+        if let Some(author) = &self.author {
+            args.push(format!("--author={}", author));
+        }
+        if let Some(date) = &self.date {
+            args.push(format!("--date={}", date));
+        }
+        for trailer in &self.trailers {
+            args.push(format!("--trailer={}", trailer));
+        }
+        args
+    }
+}
+
+/// Turns a `pairs` handle, a `.mailmap` name or email, or a literal
+/// `Name <email>` into a full identity.
+fn resolve_co_author(config: &CommitConfig, repo_path: &Path, key: &str) -> Result<String> {
+    if let Some(identity) = config.pairs.get(key) {
+        return Ok(identity.clone());
+    }
+    if key.contains('<') && key.trim_end().ends_with('>') {
+        return Ok(key.trim().to_string());
+    }
+    match mailmap::lookup(repo_path, key)? {
+        Some(identity) => Ok(identity),
+        None => bail!(
+            "Unknown co-author '{}'. Add it to [commit.pairs] or .mailmap, or pass 'Name <email>'.",
+            key
+        ),
+    }
+}
+
+/// A `git commit` that ran but did not create a commit, typically because a
+/// pre-commit or commit-msg hook rejected it.
+#[derive(Debug)]
+pub struct CommitRejected {
+    pub command: String,
+    pub status: ExitStatus,
+    /// Everything git and its hooks printed, stdout first.
+    pub output: String,
+}
+
+impl fmt::Display for CommitRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' failed with {}", self.command, self.status)?;
+        if !self.output.is_empty() {
+            write!(f, ":\n{}", self.output)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CommitRejected {}
+
+/// Runs `git commit`, passing the message on stdin so multi-line bodies and
+/// leading dashes reach git unchanged. A non-zero exit is reported as
+/// [`CommitRejected`] so callers can show the hook output and recover.
+fn run_commit(
+    repo_path: &Path,
+    message: &str,
+    amend: bool,
+    options: &CommitOptions,
+) -> Result<Output> {
+    let option_args = options.args();
+    let mut args = vec!["commit"];
+    if amend {
+        args.push("--amend");
+    }
+    args.extend(["-F", "-"]);
+    args.extend(option_args.iter().map(String::as_str));
+    let output = spawn_git_command(repo_path, &args, Some(message.as_bytes()))?;
+    if !output.status.success() {
+        let output_text = [&output.stdout, &output.stderr]
+            .iter()
+            .map(|bytes| String::from_utf8_lossy(bytes).trim().to_string())
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        return Err(CommitRejected {
+            command: format!("git {}", args.join(" ")),
+            status: output.status,
+            output: output_text,
+        }
+        .into());
     }
-    summary.binary_file_changes.sort();
-    summary.structure_changes.sort();
-    Ok(summary)
+    Ok(output)
 }
 
-pub fn commit_staged_files(repo_path: &Path, message: &str) -> Result<String, anyhow::Error> {
+pub fn commit_staged_files(
+    repo_path: &Path,
+    message: &str,
+    options: &CommitOptions,
+) -> Result<String, anyhow::Error> {
     if message.trim().is_empty() {
         bail!("Commit message cannot be empty.");
     }
-    let output = execute_git_command(repo_path, &["commit", "-m", message])
-        .context("Failed to commit staged files")?;
-    let stdout_str = str::from_utf8(&output.stdout)
-        .unwrap_or("[non-utf8 stdout from git commit]")
-        .trim();
-    Ok(stdout_str.to_string())
+    let output = run_commit(repo_path, message, false, options)?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
 pub fn get_previous_commit_message(repo_path: &Path) -> Result<Option<String>, anyhow::Error> {
     match execute_git_command(repo_path, &["log", "-1", "--pretty=%B"]) {
         Ok(output) => {
-            let message = str::from_utf8(&output.stdout)
-                .context("Failed to read git log output as UTF-8 for previous message")?
-                .trim()
-                .to_string();
+            let message = String::from_utf8_lossy(&output.stdout).trim().to_string();
             if message.is_empty() && !output.status.success() {
                 Ok(None)
             } else if message.is_empty() && output.status.success() {
@@ -390,21 +835,150 @@ pub fn get_previous_commit_message(repo_path: &Path) -> Result<Option<String>, a
     }
 }
 
-pub fn amend_commit(repo_path: &Path, message: &str) -> Result<String, anyhow::Error> {
+pub fn amend_commit(
+    repo_path: &Path,
+    message: &str,
+    options: &CommitOptions,
+) -> Result<String, anyhow::Error> {
     if message.trim().is_empty() {
         bail!("Commit message for amend cannot be empty.");
     }
-    let output = execute_git_command(repo_path, &["commit", "--amend", "-m", message])
-        .with_context(|| {
-            format!(
-                "Failed to execute 'git commit --amend -m \"{}\"' in {:?}",
-                message, repo_path
-            )
-        })?;
-    let stdout_str = str::from_utf8(&output.stdout)
-        .unwrap_or("[non-utf8 stdout from git commit --amend]")
-        .trim();
-    Ok(stdout_str.to_string())
+    let output = run_commit(repo_path, message, true, options)?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The repository's git directory. For a linked worktree this is the
+/// worktree's own directory under `.git/worktrees/`.
+pub fn get_git_dir(repo_path: &Path) -> Result<PathBuf> {
+    let output = execute_git_command(repo_path, &["rev-parse", "--git-dir"])
+        .context("Failed to locate the git directory")?;
+    let git_dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    Ok(repo_path.join(git_dir))
+}
+
+/// Writes `message` to `AI_COMMIT_MSG` in the git directory so it can be
+/// committed later with `git commit -F`. Returns the file's path.
+pub fn save_commit_message(repo_path: &Path, message: &str) -> Result<PathBuf> {
+    let path = get_git_dir(repo_path)?.join("AI_COMMIT_MSG");
+    let mut contents = message.trim_end().to_string();
+    contents.push('\n');
+    fs::write(&path, contents)
+        .with_context(|| format!("Failed to save the commit message to {}", path.display()))?;
+    Ok(path)
+}
+
+/// The installed git's version, e.g. `2.43.0`.
+pub fn get_git_version() -> Result<String> {
+    let output = Command::new("git")
+        .arg("--version")
+        .output()
+        .context("Failed to execute git. Ensure 'git' is installed and in your PATH.")?;
+    if !output.status.success() {
+        bail!("'git --version' failed with {}", output.status);
+    }
+    let version = String::from_utf8_lossy(&output.stdout);
+    Ok(version
+        .trim()
+        .trim_start_matches("git version ")
+        .to_string())
+}
+
+/// The hooks that run during `git commit`.
+const COMMIT_HOOKS: [&str; 4] = [
+    "pre-commit",
+    "prepare-commit-msg",
+    "commit-msg",
+    "post-commit",
+];
+
+/// The commit hooks present in the hooks directory (honouring
+/// `core.hooksPath`), with whether each is executable.
+pub fn get_commit_hooks(repo_path: &Path) -> Result<Vec<(String, bool)>> {
+    let output = execute_git_command(repo_path, &["rev-parse", "--git-path", "hooks"])
+        .context("Failed to locate the hooks directory")?;
+    let hooks_dir = repo_path.join(String::from_utf8_lossy(&output.stdout).trim());
+    let mut hooks = Vec::new();
+    for name in COMMIT_HOOKS {
+        let Ok(metadata) = fs::metadata(hooks_dir.join(name)) else {
+            continue;
+        };
+        #[cfg(unix)]
+        let executable = {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.permissions().mode() & 0o111 != 0
+        };
+        #[cfg(not(unix))]
+        let executable = metadata.is_file();
+        hooks.push((name.to_string(), executable));
+    }
+    Ok(hooks)
+}
+
+/// The merge, rebase or similar operation the repository is in the middle
+/// of, if any.
+pub fn get_operation_in_progress(repo_path: &Path) -> Result<Option<&'static str>> {
+    let git_dir = get_git_dir(repo_path)?;
+    let markers = [
+        ("MERGE_HEAD", "merge"),
+        ("rebase-merge", "rebase"),
+        ("rebase-apply", "rebase"),
+        ("CHERRY_PICK_HEAD", "cherry-pick"),
+        ("REVERT_HEAD", "revert"),
+        ("BISECT_LOG", "bisect"),
+    ];
+    Ok(markers
+        .iter()
+        .find(|(marker, _)| git_dir.join(marker).exists())
+        .map(|&(_, operation)| operation))
+}
+
+/// The committer identity git would record, or an error when `user.name`
+/// or `user.email` is missing.
+pub fn get_committer_ident(repo_path: &Path) -> Result<String> {
+    let output = execute_git_command(repo_path, &["var", "GIT_COMMITTER_IDENT"])
+        .context("No committer identity; set user.name and user.email")?;
+    let ident = String::from_utf8_lossy(&output.stdout);
+    // Drop the timestamp and time zone that follow the email.
+    Ok(match ident.rfind('>') {
+        Some(end) => ident[..=end].to_string(),
+        None => ident.trim().to_string(),
+    })
+}
+
+/// The checked-out branch, or `None` on a detached HEAD.
+pub fn get_current_branch(repo_path: &Path) -> Result<Option<String>> {
+    let output = git_command(repo_path)
+        .args(["symbolic-ref", "--quiet", "--short", "HEAD"])
+        .output()
+        .context("Failed to execute git symbolic-ref")?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((!branch.is_empty()).then_some(branch))
+}
+
+/// Returns `(sha, subject)` for up to `max_count` non-merge commits reachable
+/// from HEAD, newest first.
+pub fn get_commit_subjects(repo_path: &Path, max_count: usize) -> Result<Vec<(String, String)>> {
+    let max_count_arg = format!("--max-count={}", max_count);
+    let output = execute_git_command(
+        repo_path,
+        &["log", "--no-merges", &max_count_arg, "--format=%H%x00%s"],
+    )
+    .context("Failed to read commit history")?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('\0'))
+        .map(|(sha, subject)| (sha.to_string(), subject.to_string()))
+        .collect())
+}
+
+/// The patch a commit introduced, in the same format as `git diff --staged`.
+pub fn get_commit_diff(repo_path: &Path, sha: &str) -> Result<String> {
+    let output = execute_git_command(repo_path, &["show", "--format=", "--no-color", sha])
+        .with_context(|| format!("Failed to get diff of commit {}", sha))?;
+    Ok(decode_diff(&output.stdout))
 }
 
 #[cfg(test)]
@@ -649,6 +1223,7 @@ mod tests {
         let expected = StagedChangesSummary {
             binary_file_changes: vec!["added binary file: new.bin".to_string()],
             structure_changes: vec![],
+            files: summary.files.clone(),
         };
         assert_eq!(summary, expected);
         temp_dir.close()?;
@@ -666,6 +1241,7 @@ mod tests {
         let expected = StagedChangesSummary {
             binary_file_changes: vec!["modified binary file: app.exe".to_string()],
             structure_changes: vec![],
+            files: summary.files.clone(),
         };
         assert_eq!(summary, expected);
         temp_dir.close()?;
@@ -683,6 +1259,7 @@ mod tests {
         let expected = StagedChangesSummary {
             binary_file_changes: vec![],
             structure_changes: vec!["deleted file: old.txt".to_string()],
+            files: summary.files.clone(),
         };
         assert_eq!(summary, expected);
         temp_dir.close()?;
@@ -700,6 +1277,7 @@ mod tests {
         let expected = StagedChangesSummary {
             binary_file_changes: vec![],
             structure_changes: vec!["deleted file: old.bin".to_string()],
+            files: summary.files.clone(),
         };
         assert_eq!(summary, expected);
         temp_dir.close()?;
@@ -717,6 +1295,7 @@ mod tests {
         let expected = StagedChangesSummary {
             binary_file_changes: vec![],
             structure_changes: vec!["renamed: original.txt to renamed.txt".to_string()],
+            files: summary.files.clone(),
         };
         assert_eq!(summary, expected);
         temp_dir.close()?;
@@ -736,6 +1315,7 @@ mod tests {
                 "renamed binary file: original.dat to renamed.dat".to_string(),
             ],
             structure_changes: vec!["renamed: original.dat to renamed.dat".to_string()],
+            files: summary.files.clone(),
         };
         assert_eq!(summary, expected);
         temp_dir.close()?;
@@ -846,6 +1426,7 @@ mod tests {
                 "renamed binary file: src/old_file.bin to src/new_file.bin".to_string(),
             ],
             structure_changes: vec!["renamed: src/old_file.bin to src/new_file.bin".to_string()],
+            files: summary.files.clone(),
         };
         assert_eq!(summary, expected);
         temp_dir.close()?;
@@ -859,7 +1440,8 @@ mod tests {
         setup_git_repo(repo_path)?;
         stage_new_file(repo_path, "commit_me.txt", b"content to commit")?;
         let commit_message = "feat: Add commit_me.txt";
-        let commit_output = commit_staged_files(repo_path, commit_message)?;
+        let commit_output =
+            commit_staged_files(repo_path, commit_message, &CommitOptions::default())?;
 
         assert!(
             commit_output.contains("main") || commit_output.contains("master"),
@@ -890,7 +1472,7 @@ mod tests {
         let repo_path = temp_dir.path();
         setup_git_repo(repo_path)?;
         stage_new_file(repo_path, "another.txt", b"content")?;
-        let result = commit_staged_files(repo_path, " ");
+        let result = commit_staged_files(repo_path, " ", &CommitOptions::default());
         assert!(result.is_err());
         if let Err(e) = result {
             assert!(e.to_string().contains("Commit message cannot be empty."));
@@ -952,15 +1534,19 @@ mod tests {
         let temp_dir = TempDir::new()?;
         let repo_path = temp_dir.path();
         setup_git_repo(repo_path)?;
-        let result = amend_commit(repo_path, "fix: Amending non-existent commit");
+        let result = amend_commit(
+            repo_path,
+            "fix: Amending non-existent commit",
+            &CommitOptions::default(),
+        );
         assert!(result.is_err());
         if let Err(e) = result {
-            let err_string_lower = e.to_string().to_lowercase();
             assert!(
-                err_string_lower.contains("git commit --amend -m"),
-                "Error message did not contain 'git commit --amend -m'. Actual: {}",
-                err_string_lower
+                e.to_string().contains("git commit --amend -F -"),
+                "Error message did not contain 'git commit --amend -F -'. Actual: {}",
+                e
             );
+            let err_string_lower = e.to_string().to_lowercase();
             assert!(
                 err_string_lower.contains("failed"),
                 "Error message did not contain 'failed'. Actual: {}",
@@ -991,7 +1577,7 @@ mod tests {
         stage_file_changes(repo_path, "first.txt", b"updated content1")?;
 
         let amend_message = "fix: Update first.txt with new content";
-        let amend_output = amend_commit(repo_path, amend_message)?;
+        let amend_output = amend_commit(repo_path, amend_message, &CommitOptions::default())?;
         assert!(
             amend_output.contains("1 file changed")
                 || amend_output.contains(amend_message)
@@ -1011,7 +1597,7 @@ mod tests {
         let repo_path = temp_dir.path();
         setup_git_repo(repo_path)?;
         create_and_commit_file(repo_path, "another.txt", b"content")?;
-        let result = amend_commit(repo_path, " ");
+        let result = amend_commit(repo_path, " ", &CommitOptions::default());
         assert!(result.is_err());
         if let Err(e) = result {
             assert!(
@@ -1022,4 +1608,567 @@ mod tests {
         temp_dir.close()?;
         Ok(())
     }
+
+    #[test]
+    fn test_get_commit_subjects_and_diff() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path();
+        setup_git_repo(repo_path)?;
+        create_and_commit_file(repo_path, "a.txt", b"one\n")?;
+        stage_file_changes(repo_path, "a.txt", b"two\n")?;
+        run_command_in_dir(repo_path, "git", &["commit", "-m", "fix: Correct a.txt"])?;
+
+        let subjects = get_commit_subjects(repo_path, 10)?;
+        assert_eq!(subjects.len(), 2);
+        assert_eq!(subjects[0].1, "fix: Correct a.txt");
+        assert_eq!(subjects[1].1, "Initial commit");
+        assert_eq!(get_commit_subjects(repo_path, 1)?.len(), 1);
+
+        assert_eq!(get_current_branch(repo_path)?.as_deref(), Some("main"));
+        run_command_in_dir(repo_path, "git", &["checkout", "--detach"])?;
+        assert_eq!(get_current_branch(repo_path)?, None);
+
+        let diff = get_commit_diff(repo_path, &subjects[0].0)?;
+        assert!(diff.starts_with("diff --git a/a.txt b/a.txt"));
+        assert!(diff.contains("-one\n+two"));
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    fn staged_file<'a>(summary: &'a StagedChangesSummary, path: &str) -> &'a FileChange {
+        summary
+            .files
+            .iter()
+            .find(|file| file.path == path)
+            .unwrap_or_else(|| panic!("{} missing from {:?}", path, summary.files))
+    }
+
+    #[test]
+    fn test_file_changes_counts_modes_and_renames() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path();
+        setup_git_repo(repo_path)?;
+        create_and_commit_file(repo_path, "run.sh", b"echo hi\n")?;
+        create_and_commit_file(repo_path, "old_name.txt", b"one\ntwo\nthree\n")?;
+
+        stage_new_file(repo_path, "notes.txt", b"first\nsecond\n")?;
+        run_command_in_dir(repo_path, "git", &["update-index", "--chmod=+x", "run.sh"])?;
+        stage_rename(repo_path, "old_name.txt", "new_name.txt")?;
+        stage_new_file(repo_path, "logo.bin", &[0x00, 0xFF, 0x00])?;
+
+        let summary = get_staged_changes_summary(repo_path)?;
+        assert_eq!(summary.files.len(), 4);
+
+        let notes = staged_file(&summary, "notes.txt");
+        assert_eq!(notes.status, FileStatus::Added);
+        assert_eq!((notes.added, notes.removed), (Some(2), Some(0)));
+        assert_eq!(notes.mode_change, None);
+
+        let script = staged_file(&summary, "run.sh");
+        assert_eq!(script.status, FileStatus::Modified);
+        assert_eq!(script.kind, FileKind::Executable);
+        assert_eq!(
+            script.mode_change,
+            Some(ModeChange {
+                old: "100644".to_string(),
+                new: "100755".to_string()
+            })
+        );
+
+        let renamed = staged_file(&summary, "new_name.txt");
+        assert_eq!(renamed.status, FileStatus::Renamed);
+        assert_eq!(renamed.old_path.as_deref(), Some("old_name.txt"));
+        assert_eq!((renamed.added, renamed.removed), (Some(0), Some(0)));
+
+        let binary = staged_file(&summary, "logo.bin");
+        assert_eq!((binary.added, binary.removed), (None, None));
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_changes_symlinks_submodules_and_lfs() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path();
+        setup_git_repo(repo_path)?;
+        let old_sha = "1111111111111111111111111111111111111111";
+        let new_sha = "2222222222222222222222222222222222222222";
+        let gitlink = |sha: &str| format!("160000,{},vendor/lib", sha);
+        run_command_in_dir(
+            repo_path,
+            "git",
+            &["update-index", "--add", "--cacheinfo", &gitlink(old_sha)],
+        )?;
+        create_and_commit_file(repo_path, "README.md", b"readme\n")?;
+
+        run_command_in_dir(
+            repo_path,
+            "git",
+            &["update-index", "--cacheinfo", &gitlink(new_sha)],
+        )?;
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("README.md", repo_path.join("docs_link"))?;
+            run_command_in_dir(repo_path, "git", &["add", "docs_link"])?;
+        }
+        stage_new_file(
+            repo_path,
+            "model.onnx",
+            b"version https://git-lfs.github.com/spec/v1\n\
+              oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+              size 12345\n",
+        )?;
+
+        let summary = get_staged_changes_summary(repo_path)?;
+
+        let submodule = staged_file(&summary, "vendor/lib");
+        assert_eq!(submodule.kind, FileKind::Submodule);
+        assert_eq!(
+            submodule.submodule,
+            Some(SubmoduleChange {
+                old_sha: Some(old_sha.to_string()),
+                new_sha: Some(new_sha.to_string()),
+            })
+        );
+        assert!(!submodule.lfs_pointer);
+
+        let pointer = staged_file(&summary, "model.onnx");
+        assert!(pointer.lfs_pointer);
+
+        #[cfg(unix)]
+        {
+            let link = staged_file(&summary, "docs_link");
+            assert_eq!(link.kind, FileKind::Symlink);
+            assert!(!link.lfs_pointer);
+        }
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_diff_summarizes_non_utf8_files() {
+        let mut raw = b"diff --git a/ok.txt b/ok.txt\n@@ -1 +1 @@\n-old\n+new\n".to_vec();
+        raw.extend_from_slice(b"diff --git a/caf\xe9.txt b/caf\xe9.txt\n--- a/caf\xe9.txt\n");
+        raw.extend_from_slice(
+            b"+++ b/caf\xe9.txt\n@@ -1 +1,2 @@\n-na\xefve\n+cr\xe8me\n+br\xfbl\xe9e\n",
+        );
+        assert_eq!(
+            decode_diff(&raw),
+            "diff --git a/ok.txt b/ok.txt\n@@ -1 +1 @@\n-old\n+new\n\
+             diff --git a/caf\u{FFFD}.txt b/caf\u{FFFD}.txt\n\
+             --- a/caf\u{FFFD}.txt\n\
+             +++ b/caf\u{FFFD}.txt\n\
+             [Content is not valid UTF-8: 2 added and 1 removed lines not shown]\n"
+        );
+        assert_eq!(decode_diff(b""), "");
+    }
+
+    #[test]
+    fn test_decode_diff_keeps_utf8_content_of_non_utf8_paths() {
+        let raw = b"diff --git a/caf\xe9.txt b/caf\xe9.txt\n--- /dev/null\n+++ b/caf\xe9.txt\n@@ -0,0 +1 @@\n+hello\n";
+        assert_eq!(
+            decode_diff(raw),
+            "diff --git a/caf\u{FFFD}.txt b/caf\u{FFFD}.txt\n\
+             --- /dev/null\n\
+             +++ b/caf\u{FFFD}.txt\n\
+             @@ -0,0 +1 @@\n+hello\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_names_and_contents_do_not_abort() -> Result<(), anyhow::Error> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path();
+        setup_git_repo(repo_path)?;
+        let latin1_name = OsStr::from_bytes(b"caf\xe9.txt");
+        create_and_commit_file(repo_path, "notes.txt", b"plain\n")?;
+        fs::write(repo_path.join(latin1_name), b"cr\xe8me br\xfbl\xe9e\n")?;
+        fs::write(repo_path.join("notes.txt"), b"na\xefve\n")?;
+        let utf8_content_name = OsStr::from_bytes(b"r\xe9sum\xe9.txt");
+        fs::write(repo_path.join(utf8_content_name), "hello\n")?;
+        let status = Command::new("git")
+            .current_dir(repo_path)
+            .arg("add")
+            .arg(latin1_name)
+            .arg("notes.txt")
+            .arg(utf8_content_name)
+            .status()?;
+        assert!(status.success());
+
+        assert!(has_staged_files(repo_path)?);
+        let diff = get_staged_diff(repo_path)?;
+        assert!(diff.contains("diff --git a/caf\u{FFFD}.txt b/caf\u{FFFD}.txt"));
+        assert!(
+            diff.contains("[Content is not valid UTF-8: 1 added and 0 removed lines not shown]")
+        );
+        assert!(
+            diff.contains("[Content is not valid UTF-8: 1 added and 1 removed lines not shown]")
+        );
+        assert!(diff.contains("+++ b/r\u{FFFD}sum\u{FFFD}.txt\n@@ -0,0 +1 @@\n+hello\n"));
+
+        let summary = get_staged_changes_summary(repo_path)?;
+        let added = staged_file(&summary, "caf\u{FFFD}.txt");
+        assert_eq!(added.status, FileStatus::Added);
+        assert_eq!((added.added, added.removed), (Some(1), Some(0)));
+
+        run_command_in_dir(repo_path, "git", &["commit", "-m", "Add files"])?;
+        let status = Command::new("git")
+            .current_dir(repo_path)
+            .args(["rm", "--quiet"])
+            .arg(latin1_name)
+            .status()?;
+        assert!(status.success());
+        let summary = get_staged_changes_summary(repo_path)?;
+        assert_eq!(
+            summary.structure_changes,
+            vec!["deleted file: caf\u{FFFD}.txt".to_string()]
+        );
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_backend_selects_configured_backend() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path();
+        setup_git_repo(repo_path)?;
+        create_and_commit_file(repo_path, "a.txt", b"one\n")?;
+        stage_file_changes(repo_path, "a.txt", b"two\n")?;
+
+        let cli = open_backend(&GitConfig::default(), repo_path)?;
+        assert!(cli.has_staged_files()?);
+        assert_eq!(cli.current_branch()?.as_deref(), Some("main"));
+
+        let gix_config = GitConfig {
+            backend: GitBackendKind::Gix,
+        };
+        let gix = open_backend(&gix_config, repo_path);
+        if cfg!(feature = "gix") {
+            assert!(gix?.has_staged_files()?);
+        } else {
+            let err = gix
+                .err()
+                .expect("gix backend must fail without the feature");
+            assert!(err.to_string().contains("--features gix"));
+        }
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "gix")]
+    #[test]
+    fn test_gix_backend_matches_cli() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path();
+        setup_git_repo(repo_path)?;
+        create_and_commit_file(repo_path, "run.sh", b"echo hi\n")?;
+        create_and_commit_file(repo_path, "to_delete.txt", b"bye\n")?;
+        create_and_commit_file(
+            repo_path,
+            "old_name.txt",
+            b"one\ntwo\nthree\nfour\nfive\nsix\n",
+        )?;
+        create_and_commit_file(
+            repo_path,
+            "src/lib.rs",
+            b"fn a() {}\nfn b() {}\nfn c() {}\nfn d() {}\nfn e() {}\nfn f() {}\nfn g() {}\nfn h() {}\n",
+        )?;
+        create_and_commit_file(repo_path, "data.bin", &[0x00, 0x01])?;
+
+        stage_new_file(repo_path, "notes.txt", b"first\nno newline")?;
+        stage_new_file(repo_path, "empty.txt", b"")?;
+        stage_new_file(repo_path, "new.bin", &[0x00, 0xFF, 0x00])?;
+        stage_file_changes(
+            repo_path,
+            "src/lib.rs",
+            b"fn a() {}\nfn b() {}\nfn c2() {}\nfn d() {}\nfn e() {}\nfn f() {}\nfn g() {}\nfn h2() {}\n",
+        )?;
+        stage_file_changes(repo_path, "data.bin", &[0x00, 0x02])?;
+        run_command_in_dir(repo_path, "git", &["update-index", "--chmod=+x", "run.sh"])?;
+        stage_deletion(repo_path, "to_delete.txt")?;
+        stage_rename(repo_path, "old_name.txt", "new_name.txt")?;
+        stage_new_file(
+            repo_path,
+            "model.onnx",
+            b"version https://git-lfs.github.com/spec/v1\noid sha256:abc\nsize 1\n",
+        )?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            std::os::unix::fs::symlink("run.sh", repo_path.join("link"))?;
+            let latin1_name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+            fs::write(repo_path.join(latin1_name), b"cr\xe8me\n")?;
+            let status = Command::new("git")
+                .current_dir(repo_path)
+                .args(["add", "link"])
+                .arg(latin1_name)
+                .status()?;
+            assert!(status.success());
+        }
+
+        let cli = CliBackend::new(repo_path);
+        let gix = gitoxide::GitoxideBackend::open(repo_path)?;
+        assert_eq!(gix.staged_diff()?, cli.staged_diff()?);
+        assert_eq!(gix.staged_changes_summary()?, cli.staged_changes_summary()?);
+        assert_eq!(gix.has_staged_files()?, cli.has_staged_files()?);
+        assert_eq!(gix.current_branch()?, cli.current_branch()?);
+        assert_eq!(
+            gix.previous_commit_message()?,
+            cli.previous_commit_message()?
+        );
+
+        // The staged changes are read once, by the first query.
+        let diff = gix.staged_diff()?;
+        stage_new_file(repo_path, "later.txt", b"later\n")?;
+        assert_eq!(gix.staged_diff()?, diff);
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_repo_root_from_subdirectory_and_worktree() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path().join("repo");
+        fs::create_dir_all(&repo_path)?;
+        setup_git_repo(&repo_path)?;
+        create_and_commit_file(&repo_path, "src/lib.rs", b"fn main() {}\n")?;
+
+        let root = fs::canonicalize(&repo_path)?;
+        assert_eq!(resolve_repo_root(&repo_path.join("src"))?, root);
+
+        let worktree_path = temp_dir.path().join("linked");
+        run_command_in_dir(
+            &repo_path,
+            "git",
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                "feature",
+                worktree_path.to_str().unwrap(),
+            ],
+        )?;
+        let worktree_root = fs::canonicalize(&worktree_path)?;
+        assert_eq!(
+            resolve_repo_root(&worktree_path.join("src"))?,
+            worktree_root
+        );
+        assert_eq!(
+            get_current_branch(&worktree_root)?.as_deref(),
+            Some("feature")
+        );
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_repo_root_outside_work_tree() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        let plain_dir = temp_dir.path().join("plain");
+        fs::create_dir_all(&plain_dir)?;
+        let message = resolve_repo_root(&plain_dir).unwrap_err().to_string();
+        assert!(message.contains("Not a git repository"), "{}", message);
+        assert!(message.contains("-C <path>"), "{}", message);
+
+        let repo_path = temp_dir.path().join("repo");
+        fs::create_dir_all(&repo_path)?;
+        setup_git_repo(&repo_path)?;
+        let message = resolve_repo_root(&repo_path.join(".git"))
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("not inside a work tree"), "{}", message);
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_options_are_passed_to_git() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path();
+        setup_git_repo(repo_path)?;
+        stage_new_file(repo_path, "a.txt", b"a")?;
+        let options = CommitOptions {
+            signoff: true,
+            author: Some("Pat Doe <pat@example.com>".to_string()),
+            date: Some("2024-02-03T04:05:06Z".to_string()),
+            trailers: vec!["Co-authored-by: Sam Roe <sam@example.com>".to_string()],
+            ..CommitOptions::default()
+        };
+        let message = "-feat: Start with a dash\n\nBody line one.\n- bullet";
+        commit_staged_files(repo_path, message, &options)?;
+
+        let log = execute_git_command(repo_path, &["log", "-1", "--format=%an <%ae>%n%aI%n%B"])?;
+        let log = String::from_utf8_lossy(&log.stdout);
+        let mut lines = log.lines();
+        assert_eq!(lines.next(), Some("Pat Doe <pat@example.com>"));
+        assert_eq!(lines.next(), Some("2024-02-03T04:05:06+00:00"));
+        let body: Vec<&str> = lines.collect();
+        assert_eq!(
+            body[..4],
+            ["-feat: Start with a dash", "", "Body line one.", "- bullet"]
+        );
+        assert!(body.contains(&"Co-authored-by: Sam Roe <sam@example.com>"));
+        assert!(body.contains(&"Signed-off-by: Test User <test@example.com>"));
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_commit_no_verify_skips_hooks() -> Result<(), anyhow::Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path();
+        setup_git_repo(repo_path)?;
+        let hook = repo_path.join(".git/hooks/pre-commit");
+        fs::write(&hook, "#!/bin/sh\necho 'lint failed' >&2\nexit 1\n")?;
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755))?;
+        stage_new_file(repo_path, "a.txt", b"a")?;
+
+        let err =
+            commit_staged_files(repo_path, "feat: Add a", &CommitOptions::default()).unwrap_err();
+        let rejected = err
+            .downcast_ref::<CommitRejected>()
+            .expect("hook failure should be a CommitRejected");
+        assert_eq!(rejected.output, "lint failed");
+        assert!(rejected.command.starts_with("git commit -F -"));
+
+        let options = CommitOptions {
+            no_verify: true,
+            ..CommitOptions::default()
+        };
+        commit_staged_files(repo_path, "feat: Add a", &options)?;
+        assert_eq!(
+            get_previous_commit_message(repo_path)?.as_deref(),
+            Some("feat: Add a")
+        );
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_commit_message_in_worktree() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path().join("repo");
+        fs::create_dir_all(&repo_path)?;
+        setup_git_repo(&repo_path)?;
+        create_and_commit_file(&repo_path, "a.txt", b"a")?;
+
+        let saved = save_commit_message(&repo_path, "feat: Add a\n\nBody.\n\n")?;
+        assert_eq!(saved, repo_path.join(".git/AI_COMMIT_MSG"));
+        assert_eq!(fs::read_to_string(&saved)?, "feat: Add a\n\nBody.\n");
+
+        let worktree_path = temp_dir.path().join("linked");
+        run_command_in_dir(
+            &repo_path,
+            "git",
+            &["worktree", "add", "-q", worktree_path.to_str().unwrap()],
+        )?;
+        let saved = save_commit_message(&worktree_path, "fix: In worktree")?;
+        assert_eq!(
+            fs::canonicalize(&saved)?,
+            fs::canonicalize(repo_path.join(".git/worktrees/linked/AI_COMMIT_MSG"))?
+        );
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_repository_diagnostics() -> Result<(), anyhow::Error> {
+        use std::os::unix::fs::PermissionsExt;
+        let temp_dir = TempDir::new()?;
+        let repo_path = temp_dir.path();
+        setup_git_repo(repo_path)?;
+
+        assert!(get_git_version()?.starts_with(|c: char| c.is_ascii_digit()));
+        assert_eq!(
+            get_committer_ident(repo_path)?,
+            "Test User <test@example.com>"
+        );
+
+        let hooks_dir = repo_path.join("custom-hooks");
+        fs::create_dir_all(&hooks_dir)?;
+        fs::write(hooks_dir.join("commit-msg"), "#!/bin/sh\n")?;
+        fs::set_permissions(
+            hooks_dir.join("commit-msg"),
+            fs::Permissions::from_mode(0o755),
+        )?;
+        fs::write(hooks_dir.join("pre-commit"), "#!/bin/sh\n")?;
+        fs::set_permissions(
+            hooks_dir.join("pre-commit"),
+            fs::Permissions::from_mode(0o644),
+        )?;
+        run_command_in_dir(
+            repo_path,
+            "git",
+            &["config", "core.hooksPath", "custom-hooks"],
+        )?;
+        assert_eq!(
+            get_commit_hooks(repo_path)?,
+            vec![
+                ("pre-commit".to_string(), false),
+                ("commit-msg".to_string(), true)
+            ]
+        );
+
+        assert_eq!(get_operation_in_progress(repo_path)?, None);
+        fs::write(repo_path.join(".git/MERGE_HEAD"), "0000\n")?;
+        assert_eq!(get_operation_in_progress(repo_path)?, Some("merge"));
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_options_from_config() -> Result<(), anyhow::Error> {
+        let temp_dir = TempDir::new()?;
+        fs::write(
+            temp_dir.path().join(".mailmap"),
+            "Alice Smith <alice@example.com> <asmith@old.example>\n",
+        )?;
+        let mut config = CommitConfig {
+            sign: true,
+            trailers: vec!["Reviewed-by:Kim <kim@example.com>".to_string()],
+            co_authors: vec![
+                "bob".to_string(),
+                "alice".to_string(),
+                "Eve <eve@example.com>".to_string(),
+                "alice@example.com".to_string(),
+            ],
+            ..CommitConfig::default()
+        };
+        config
+            .pairs
+            .insert("bob".to_string(), "Bob Jones <bob@example.com>".to_string());
+
+        let options = CommitOptions::from_config(&config, temp_dir.path())?;
+        assert_eq!(
+            options.trailers,
+            vec![
+                "Reviewed-by: Kim <kim@example.com>",
+                "Co-authored-by: Bob Jones <bob@example.com>",
+                "Co-authored-by: Alice Smith <alice@example.com>",
+                "Co-authored-by: Eve <eve@example.com>",
+            ]
+        );
+        assert!(options.args().contains(&"--gpg-sign".to_string()));
+
+        config.co_authors = vec!["mallory".to_string()];
+        let err = CommitOptions::from_config(&config, temp_dir.path()).unwrap_err();
+        assert!(err.to_string().contains("Unknown co-author 'mallory'"));
+
+        config.co_authors.clear();
+        config.trailers = vec!["no separator".to_string()];
+        let err = CommitOptions::from_config(&config, temp_dir.path()).unwrap_err();
+        assert!(err.to_string().contains("Invalid trailer"));
+        temp_dir.close()?;
+        Ok(())
+    }
 }
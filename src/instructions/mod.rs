@@ -0,0 +1,150 @@
+use crate::config::{self, InstructionsConfig, REPO_CONFIG_DIR};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const INSTRUCTIONS_FILE_NAME: &str = "instructions.md";
+const TRUNCATION_MARKER: &str = "[... truncated]";
+
+/// Repository-specific guidance collected from the instruction files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectGuidelines {
+    pub text: String,
+    /// Files that contributed to `text`, global first.
+    pub sources: Vec<PathBuf>,
+    /// Files that were cut to fit `instructions.max_chars`.
+    pub truncated: Vec<PathBuf>,
+}
+
+/// `instructions.md` next to the global config file.
+pub fn global_instructions_path() -> Option<PathBuf> {
+    config::global_config_path()?
+        .parent()
+        .map(|dir| dir.join(INSTRUCTIONS_FILE_NAME))
+}
+
+pub fn repo_instructions_path(repo_path: &Path) -> PathBuf {
+    repo_path.join(REPO_CONFIG_DIR).join(INSTRUCTIONS_FILE_NAME)
+}
+
+/// Cuts `text` to at most `max_chars` characters, preferring to stop at the
+/// end of a line. Returns `None` if no cut was needed.
+fn truncate(text: &str, max_chars: usize) -> Option<String> {
+    let (cut, _) = text.char_indices().nth(max_chars)?;
+    let head = &text[..cut];
+    let head = match head.rfind('\n') {
+        Some(newline) if newline > 0 => &head[..newline],
+        _ => head,
+    };
+    Some(format!("{}\n{}", head.trim_end(), TRUNCATION_MARKER))
+}
+
+fn load_from(paths: &[PathBuf], max_chars: usize) -> Result<Option<ProjectGuidelines>> {
+    let mut sections = Vec::new();
+    let mut sources = Vec::new();
+    let mut truncated = Vec::new();
+    for path in paths {
+        if !path.is_file() {
+            continue;
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read instructions file {:?}", path))?;
+        let contents = contents.trim();
+        if contents.is_empty() {
+            continue;
+        }
+        match truncate(contents, max_chars) {
+            Some(cut) => {
+                sections.push(cut);
+                truncated.push(path.clone());
+            }
+            None => sections.push(contents.to_string()),
+        }
+        sources.push(path.clone());
+    }
+    if sections.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(ProjectGuidelines {
+        text: sections.join("\n\n"),
+        sources,
+        truncated,
+    }))
+}
+
+/// Reads the global and repository instruction files. Each file is limited
+/// to `max_chars` characters; missing or empty files are skipped.
+pub fn load(config: &InstructionsConfig, repo_path: &Path) -> Result<Option<ProjectGuidelines>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let paths: Vec<PathBuf> = global_instructions_path()
+        .into_iter()
+        .chain(std::iter::once(repo_instructions_path(repo_path)))
+        .collect();
+    load_from(&paths, config.max_chars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_truncate_prefers_line_boundaries() {
+        assert_eq!(truncate("short", 10), None);
+        assert_eq!(
+            truncate("- use 'tenant'\n- mention the migration number", 20),
+            Some("- use 'tenant'\n[... truncated]".to_string())
+        );
+        assert_eq!(
+            truncate("ünïcödé wörds", 4),
+            Some("ünïc\n[... truncated]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_load_combines_files_in_order() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let global = temp_dir.path().join("global.md");
+        let repo = temp_dir.path().join("repo.md");
+        let empty = temp_dir.path().join("empty.md");
+        fs::write(&global, "Write in British English.\n")?;
+        fs::write(&repo, "Use 'tenant', not 'customer'.\n")?;
+        fs::write(&empty, "\n  \n")?;
+        let missing = temp_dir.path().join("missing.md");
+
+        let guidelines =
+            load_from(&[global.clone(), empty, missing, repo.clone()], 100)?.expect("guidelines");
+        assert_eq!(
+            guidelines.text,
+            "Write in British English.\n\nUse 'tenant', not 'customer'."
+        );
+        assert_eq!(guidelines.sources, vec![global, repo.clone()]);
+        assert!(guidelines.truncated.is_empty());
+
+        let limited = load_from(std::slice::from_ref(&repo), 8)?.expect("guidelines");
+        assert_eq!(limited.truncated, vec![repo]);
+        assert!(limited.text.ends_with(TRUNCATION_MARKER));
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disabled_or_missing_instructions() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        fs::create_dir_all(temp_dir.path().join(REPO_CONFIG_DIR))?;
+        fs::write(
+            repo_instructions_path(temp_dir.path()),
+            "Mention ticket IDs.",
+        )?;
+        let disabled = InstructionsConfig {
+            enabled: false,
+            ..InstructionsConfig::default()
+        };
+        assert_eq!(load(&disabled, temp_dir.path())?, None);
+        assert_eq!(load_from(&[temp_dir.path().join("nope.md")], 100)?, None);
+        temp_dir.close()?;
+        Ok(())
+    }
+}
@@ -1,14 +1,30 @@
 use anyhow::{Context, bail};
-use clap::Parser;
-use inquire::{InquireError, Select};
+use clap::{Parser, Subcommand};
+use inquire::{Confirm, Editor, InquireError, Select};
 use std::env;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{Notify, mpsc};
+use tokio::task::JoinHandle;
 
 mod ai;
+mod auth;
+mod cache;
+mod config;
 mod diff;
+mod doctor;
+mod eval;
 mod git;
+mod instructions;
+mod preflight;
 mod prompt;
+mod redact;
+#[cfg(test)]
+mod testing;
+mod usage;
+mod validate;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -19,11 +35,218 @@ mod prompt;
              It prioritizes speed and a tight feedback loop for the solo developer."
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Run as if ai-commit was started in PATH instead of the current directory
+    #[arg(global = true, short = 'C', long = "repo", value_name = "PATH")]
+    directory: Option<PathBuf>,
+
     #[arg(short, long)]
     interactive: bool,
 
     #[arg(short = 'a', long)]
     amend: bool,
+
+    /// Model to use as `provider/model`; repeat to build a fallback chain (overrides `models`)
+    #[arg(global = true, short, long = "model", value_name = "PROVIDER/MODEL")]
+    models: Vec<config::ModelConfig>,
+
+    /// Maximum number of attempts per AI request (overrides `retry.max_attempts`)
+    #[arg(global = true, long, value_name = "N")]
+    retries: Option<u32>,
+
+    /// Give up retrying an AI request after this many seconds (overrides `retry.deadline_secs`)
+    #[arg(global = true, long, value_name = "SECS")]
+    retry_deadline: Option<u64>,
+
+    /// Overall timeout for a single AI request in seconds (overrides `http.timeout_secs`)
+    #[arg(global = true, long, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Timeout for establishing a connection in seconds (overrides `http.connect_timeout_secs`)
+    #[arg(global = true, long, value_name = "SECS")]
+    connect_timeout: Option<u64>,
+
+    /// minijinja template to render the prompt with (overrides `prompt.template`)
+    #[arg(global = true, long, value_name = "FILE")]
+    prompt_template: Option<PathBuf>,
+
+    /// Always call the AI instead of reusing a cached response for the same changes
+    #[arg(global = true, long)]
+    no_cache: bool,
+
+    /// Wait for complete AI responses instead of streaming them (overrides `http.stream`)
+    #[arg(global = true, long)]
+    no_stream: bool,
+
+    #[command(flatten)]
+    commit: CommitArgs,
+
+    #[command(flatten)]
+    generation: GenerationArgs,
+}
+
+/// Options passed through to `git commit`; each adds to the `[commit]` config.
+#[derive(clap::Args, Debug)]
+struct CommitArgs {
+    /// GPG/SSH-sign the commit
+    #[arg(short = 'S', long = "gpg-sign")]
+    sign: bool,
+
+    /// Add a Signed-off-by trailer
+    #[arg(short = 's', long)]
+    signoff: bool,
+
+    /// Skip the pre-commit and commit-msg hooks
+    #[arg(short = 'n', long)]
+    no_verify: bool,
+
+    /// Override the commit author
+    #[arg(long, value_name = "NAME <EMAIL>")]
+    author: Option<String>,
+
+    /// Override the author date
+    #[arg(long, value_name = "DATE")]
+    date: Option<String>,
+
+    /// Add a trailer such as `Reviewed-by: Name <email>`; repeatable
+    #[arg(long = "trailer", value_name = "KEY: VALUE")]
+    trailers: Vec<String>,
+
+    /// Credit a co-author by `commit.pairs` handle, .mailmap name or email, or `Name <email>`; repeatable
+    #[arg(long = "co-author", value_name = "WHO")]
+    co_authors: Vec<String>,
+}
+
+/// Sampling parameters; each overrides its `[generation]` config key.
+#[derive(clap::Args, Debug)]
+struct GenerationArgs {
+    /// Sampling temperature between 0.0 and 2.0
+    #[arg(global = true, long, value_name = "T")]
+    temperature: Option<f32>,
+
+    /// Nucleus sampling probability mass between 0.0 and 1.0
+    #[arg(global = true, long, value_name = "P")]
+    top_p: Option<f32>,
+
+    /// Sample from the K most likely tokens (Gemini only)
+    #[arg(global = true, long, value_name = "K")]
+    top_k: Option<u32>,
+
+    /// Maximum number of tokens in the response
+    #[arg(global = true, long, value_name = "N")]
+    max_output_tokens: Option<u32>,
+
+    /// Stop generating at this sequence; repeatable, replaces `generation.stop`
+    #[arg(global = true, long = "stop", value_name = "TEXT")]
+    stop: Vec<String>,
+
+    /// Seed for repeatable sampling where the provider supports it
+    #[arg(global = true, long, value_name = "N", allow_negative_numbers = true)]
+    seed: Option<i64>,
+
+    /// Thinking token budget; 0 disables thinking, -1 lets the model decide (Gemini only)
+    #[arg(global = true, long, value_name = "N", allow_negative_numbers = true)]
+    thinking_budget: Option<i32>,
+
+    /// Raise the temperature by this much on every regeneration
+    #[arg(global = true, long, value_name = "STEP")]
+    regenerate_temperature_step: Option<f32>,
+
+    /// Safety filter level for every harm category, e.g. block_only_high (Gemini only)
+    #[arg(global = true, long, value_name = "LEVEL")]
+    safety_threshold: Option<config::SafetyThreshold>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Measure commit type accuracy of the prompt and models against a dataset
+    Eval(EvalArgs),
+    /// Print the prompt that would be sent to the AI for the staged changes
+    Prompt(PromptArgs),
+    /// Store, remove or inspect provider API keys
+    #[command(subcommand)]
+    Auth(AuthCommand),
+    /// Diagnose git, configuration, API keys, network and terminal problems
+    Doctor,
+    /// Summarize token usage and estimated spend from the usage log
+    Usage(UsageArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct UsageArgs {
+    /// Show only one breakdown: day, repo or model
+    #[arg(long, value_name = "GROUPING")]
+    by: Option<usage::GroupBy>,
+
+    /// Only count the last N days
+    #[arg(long, value_name = "N")]
+    days: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum AuthCommand {
+    /// Save an API key in the OS keyring (or the credentials file)
+    Login {
+        /// Provider the key is for; defaults to the first configured model's
+        #[arg(long, value_name = "PROVIDER")]
+        provider: Option<config::Provider>,
+
+        /// Store the key in the 0600 credentials file instead of the OS keyring
+        #[arg(long)]
+        file: bool,
+    },
+    /// Remove a stored API key from the OS keyring and the credentials file
+    Logout {
+        /// Provider the key is for; defaults to the first configured model's
+        #[arg(long, value_name = "PROVIDER")]
+        provider: Option<config::Provider>,
+    },
+    /// Show where each provider's API key would be read from
+    Status,
+}
+
+#[derive(clap::Args, Debug)]
+struct PromptArgs {
+    /// Render the prompt used when amending the last commit
+    #[arg(short = 'a', long)]
+    amend: bool,
+
+    /// Number of suggestions the prompt asks for
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 1)]
+    variations: u32,
+}
+
+#[derive(clap::Args, Debug)]
+struct EvalArgs {
+    /// JSONL file of cases with `diff`, `expected_type` and optional `expected_scope` and `reference_subject`
+    #[arg(long, value_name = "FILE", required_unless_present = "harvest")]
+    dataset: Option<PathBuf>,
+
+    /// Build the dataset from this repository's Conventional Commit history
+    #[arg(long, conflicts_with = "dataset")]
+    harvest: bool,
+
+    /// Maximum number of commits to harvest
+    #[arg(long, value_name = "N", default_value_t = 50)]
+    limit: usize,
+
+    /// Save the harvested dataset as JSONL for later runs
+    #[arg(long, value_name = "FILE", requires = "harvest")]
+    save_dataset: Option<PathBuf>,
+
+    /// Write the full results as JSON
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Name stored with the results, e.g. the prompt change being tried
+    #[arg(long)]
+    label: Option<String>,
+
+    /// Results of an earlier run to compare against
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<PathBuf>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -35,6 +258,66 @@ enum AiCommitMode {
 }
 
 impl Args {
+    fn apply_overrides(&self, config: &mut config::Config) {
+        if !self.models.is_empty() {
+            config.models = self.models.clone();
+        }
+        if let Some(retries) = self.retries {
+            config.retry.max_attempts = retries;
+        }
+        if let Some(deadline) = self.retry_deadline {
+            config.retry.deadline_secs = deadline;
+        }
+        if let Some(timeout) = self.timeout {
+            config.http.timeout_secs = timeout;
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            config.http.connect_timeout_secs = connect_timeout;
+        }
+        if self.no_stream {
+            config.http.stream = false;
+        }
+        if self.no_cache {
+            config.cache.enabled = false;
+        }
+        if let Some(template) = &self.prompt_template {
+            config.prompt.template = Some(template.clone());
+        }
+        let commit = &self.commit;
+        config.commit.sign |= commit.sign;
+        config.commit.signoff |= commit.signoff;
+        config.commit.no_verify |= commit.no_verify;
+        if let Some(author) = &commit.author {
+            config.commit.author = Some(author.clone());
+        }
+        if let Some(date) = &commit.date {
+            config.commit.date = Some(date.clone());
+        }
+        config
+            .commit
+            .trailers
+            .extend(commit.trailers.iter().cloned());
+        config
+            .commit
+            .co_authors
+            .extend(commit.co_authors.iter().cloned());
+        let generation = &self.generation;
+        let params = &mut config.generation;
+        params.temperature = generation.temperature.or(params.temperature);
+        params.top_p = generation.top_p.or(params.top_p);
+        params.top_k = generation.top_k.or(params.top_k);
+        params.max_output_tokens = generation.max_output_tokens.or(params.max_output_tokens);
+        if !generation.stop.is_empty() {
+            params.stop = generation.stop.clone();
+        }
+        params.seed = generation.seed.or(params.seed);
+        params.thinking_budget = generation.thinking_budget.or(params.thinking_budget);
+        params.regenerate_temperature_step = generation
+            .regenerate_temperature_step
+            .or(params.regenerate_temperature_step);
+        params.safety_threshold = generation.safety_threshold.or(params.safety_threshold);
+    }
+
     fn determine_mode(&self) -> AiCommitMode {
         match (self.interactive, self.amend) {
             (false, false) => AiCommitMode::Auto,
@@ -47,31 +330,404 @@ impl Args {
 
 const REGENERATE_OPTION: &str = "🔄 Regenerate suggestions";
 const CANCEL_OPTION: &str = "❌ Cancel and exit";
+const MORE_SUGGESTIONS_OPTION: &str = "⏳ Wait for more suggestions";
+const RETRY_COMMIT_OPTION: &str = "🔁 Retry the commit (after fixing the problem)";
+const EDIT_MESSAGE_OPTION: &str = "✏️ Edit the message and retry";
+const NO_VERIFY_OPTION: &str = "⚠️ Commit with --no-verify (skip hooks)";
+const SAVE_MESSAGE_OPTION: &str = "💾 Save the message for later and exit";
+const SIGINT_EXIT_CODE: i32 = 130;
 
-async fn interactive_commit_loop(
-    _repo_path: &PathBuf,
-    preprocessed_diff_text: &str,
-    changes_summary: &git::StagedChangesSummary,
-    num_variations_to_request: u32,
-    previous_message: Option<&str>,
-    mode_description: &str,
-) -> anyhow::Result<Option<String>> {
+/// Rewrites the spinner line as `<label> <status>`, showing streamed
+/// suggestions as they arrive. Token reports go to `tally`.
+fn spinner_status<'a>(label: &'a str, tally: &'a UsageTally) -> impl Fn(ai::Progress) + Sync + 'a {
+    move |progress| {
+        let status = match progress {
+            ai::Progress::Status(status) => status,
+            ai::Progress::Suggestion(suggestion) => format!("✍️ {}", suggestion),
+            ai::Progress::Usage { .. } => return tally.record(&progress),
+        };
+        print!("\r\x1b[2K{} {}", label, status);
+        let _ = io::stdout().flush();
+    }
+}
+
+/// How many waits Ctrl-C may currently cancel; see `interrupted`.
+static CANCELLABLE_WAITS: AtomicUsize = AtomicUsize::new(0);
+static CTRL_C: Notify = Notify::const_new();
+
+/// Handles Ctrl-C for the whole run. Once tokio listens for SIGINT its default
+/// action no longer applies, so outside a cancellable wait this exits the way
+/// an unhandled SIGINT would, e.g. while a hook runs.
+async fn watch_ctrl_c() {
+    while tokio::signal::ctrl_c().await.is_ok() {
+        if CANCELLABLE_WAITS.load(Ordering::SeqCst) > 0 {
+            CTRL_C.notify_waiters();
+        } else {
+            eprintln!("\n❌ Interrupted.");
+            std::process::exit(SIGINT_EXIT_CODE);
+        }
+    }
+}
+
+/// Keeps Ctrl-C from exiting the process while it is alive.
+struct CancellableWait;
+
+impl CancellableWait {
+    fn start() -> Self {
+        CANCELLABLE_WAITS.fetch_add(1, Ordering::SeqCst);
+        CancellableWait
+    }
+}
+
+impl Drop for CancellableWait {
+    fn drop(&mut self) {
+        CANCELLABLE_WAITS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Resolves when the user presses Ctrl-C while this is being awaited.
+async fn interrupted() {
+    let notified = CTRL_C.notified();
+    tokio::pin!(notified);
+    notified.as_mut().enable();
+    let _wait = CancellableWait::start();
+    notified.await;
+}
+
+/// Runs `future` until it completes or the user presses Ctrl-C, in which case
+/// the future is dropped (cancelling any in-flight request) and `None` is returned.
+async fn until_interrupted<T>(future: impl Future<Output = T>) -> Option<T> {
+    tokio::select! {
+        result = future => Some(result),
+        _ = interrupted() => None,
+    }
+}
+
+/// The tokens each model billed during one generation, collected from
+/// `Progress::Usage` so failed and cancelled generations are counted too.
+/// Cached generations bill nothing and report nothing.
+#[derive(Default)]
+struct UsageTally(Mutex<Vec<(config::ModelConfig, ai::Usage)>>);
+
+impl UsageTally {
+    fn record(&self, progress: &ai::Progress) {
+        let ai::Progress::Usage { model, usage } = progress else {
+            return;
+        };
+        let mut billed = self.0.lock().unwrap();
+        match billed
+            .iter_mut()
+            .find(|(billed_model, _)| billed_model == model)
+        {
+            Some((_, billed_usage)) => *billed_usage = *usage,
+            None => billed.push((model.clone(), *usage)),
+        }
+    }
+
+    /// Reports what has been billed since the last call.
+    fn report(&self, repo_path: &Path, config: &config::Config) {
+        let billed = std::mem::take(&mut *self.0.lock().unwrap());
+        for (model, usage) in billed {
+            report_usage(&model, usage, repo_path, config);
+        }
+    }
+}
+
+/// Prints the tokens and estimated cost `model` billed and appends them to
+/// the usage log.
+fn report_usage(
+    model: &config::ModelConfig,
+    usage: ai::Usage,
+    repo_path: &Path,
+    config: &config::Config,
+) {
+    let record = usage::UsageRecord::new(repo_path, model, usage, config);
+    if usage != ai::Usage::default() {
+        println!("🪙 {}", usage::describe(usage, record.cost_usd));
+    }
+    if config.usage.log
+        && let Some(path) = usage::log_path(&config.usage)
+        && let Err(e) = usage::append(&path, &record)
+    {
+        eprintln!("⚠️ Could not write the usage log: {:#}", e);
+    }
+}
+
+fn describe_source(generation: &ai::Generation) -> String {
+    if generation.from_cache {
+        format!("{}, cached", generation.model)
+    } else {
+        generation.model.to_string()
+    }
+}
+
+/// Asks the user to choose one of `options`. Abstracted so the commit flows
+/// can be driven without a terminal.
+trait Selector {
+    fn select(&self, message: &str, options: Vec<String>) -> Result<String, InquireError>;
+    fn confirm(&self, message: &str) -> Result<bool, InquireError>;
+    fn edit(&self, message: &str, text: &str) -> Result<String, InquireError>;
+}
+
+struct TerminalSelector;
+
+impl Selector for TerminalSelector {
+    fn select(&self, message: &str, options: Vec<String>) -> Result<String, InquireError> {
+        Select::new(message, options).prompt()
+    }
+
+    fn confirm(&self, message: &str) -> Result<bool, InquireError> {
+        Confirm::new(message).with_default(false).prompt()
+    }
+
+    fn edit(&self, message: &str, text: &str) -> Result<String, InquireError> {
+        Editor::new(message)
+            .with_predefined_text(text)
+            .with_file_extension(".txt")
+            .prompt()
+    }
+}
+
+/// Warns when `prompt` is over `preflight.max_prompt_tokens` and asks whether
+/// to send it anyway. Returns whether to go ahead.
+async fn confirm_prompt_size(
+    prompt: &str,
+    config: &config::Config,
+    selector: &dyn Selector,
+) -> anyhow::Result<bool> {
+    let Some(tokens) = preflight::oversize(config, prompt).await else {
+        return Ok(true);
+    };
+    eprintln!("⚠️ {}.", preflight::describe_oversize(config, tokens));
+    match selector.confirm("Send it anyway?") {
+        Ok(send) => Ok(send),
+        Err(InquireError::OperationCanceled | InquireError::OperationInterrupted) => Ok(false),
+        Err(ie) => Err(ie.into()),
+    }
+}
+
+/// Offers to regenerate after a failed attempt; returns whether to try again.
+fn ask_regenerate(selector: &dyn Selector, message: &str) -> anyhow::Result<bool> {
+    let options = vec![REGENERATE_OPTION.to_string(), CANCEL_OPTION.to_string()];
+    match selector.select(message, options) {
+        Ok(choice) => Ok(choice == REGENERATE_OPTION),
+        Err(InquireError::OperationCanceled | InquireError::OperationInterrupted) => Ok(false),
+        Err(ie) => Err(ie.into()),
+    }
+}
+
+/// Commits (or amends) with `message`. When git rejects the commit, shows
+/// what git and its hooks printed and, given a `selector`, offers to retry,
+/// edit the message, skip the hooks or save the message. Whenever this gives
+/// up, the message is first saved to `AI_COMMIT_MSG` in the git directory.
+fn commit_with_recovery(
+    repo_path: &Path,
+    message: &str,
+    amend: bool,
+    options: &git::CommitOptions,
+    selector: Option<&dyn Selector>,
+) -> anyhow::Result<String> {
+    let mut message = message.to_string();
+    let mut options = options.clone();
     loop {
-        let prompt_str = prompt::build_prompt(
-            preprocessed_diff_text,
-            changes_summary,
-            num_variations_to_request,
-            previous_message,
+        let result = if amend {
+            git::amend_commit(repo_path, &message, &options)
+        } else {
+            git::commit_staged_files(repo_path, &message, &options)
+        };
+        let error = match result {
+            Ok(output) => return Ok(output),
+            Err(e) => e,
+        };
+        let Some(rejected) = error.downcast_ref::<git::CommitRejected>() else {
+            return Err(preserve_message(repo_path, &message, amend, error));
+        };
+        eprintln!(
+            "\n❌ git rejected the commit ({}). A pre-commit or commit-msg hook may have failed.",
+            rejected.status
         );
+        if !rejected.output.is_empty() {
+            eprintln!("─── git output ───");
+            for line in rejected.output.lines() {
+                eprintln!("│ {}", line);
+            }
+            eprintln!("──────────────────");
+        }
+        let Some(selector) = selector else {
+            return Err(preserve_message(repo_path, &message, amend, error));
+        };
 
-        if env::var("AI_COMMIT_LOG_PROMPT").is_ok() {
-            println!("\n================ PROMPT SENT TO AI (INTERACTIVE) ================");
-            println!("{}", prompt_str);
-            println!("=================================================================\n");
+        loop {
+            let mut choices = vec![
+                RETRY_COMMIT_OPTION.to_string(),
+                EDIT_MESSAGE_OPTION.to_string(),
+            ];
+            if !options.no_verify {
+                choices.push(NO_VERIFY_OPTION.to_string());
+            }
+            choices.push(SAVE_MESSAGE_OPTION.to_string());
+            let choice =
+                match selector.select("The commit failed. What would you like to do?", choices) {
+                    Ok(choice) => choice,
+                    Err(_) => return Err(preserve_message(repo_path, &message, amend, error)),
+                };
+            if choice == RETRY_COMMIT_OPTION {
+                break;
+            } else if choice == EDIT_MESSAGE_OPTION {
+                match selector.edit("Edit the commit message:", &message) {
+                    Ok(edited) if !edited.trim().is_empty() => {
+                        message = edited.trim().to_string();
+                        break;
+                    }
+                    Ok(_) => eprintln!("⚠️ The edited message is empty; keeping the previous one."),
+                    Err(_) => eprintln!("⚠️ Edit cancelled; keeping the previous message."),
+                }
+            } else if choice == NO_VERIFY_OPTION {
+                if let Ok(true) =
+                    selector.confirm("Skip the pre-commit and commit-msg hooks for this commit?")
+                {
+                    options.no_verify = true;
+                    break;
+                }
+            } else {
+                return Err(preserve_message(repo_path, &message, amend, error));
+            }
         }
+    }
+}
+
+/// Saves `message` after a failed commit and tells the user how to use it.
+fn preserve_message(
+    repo_path: &Path,
+    message: &str,
+    amend: bool,
+    error: anyhow::Error,
+) -> anyhow::Error {
+    match git::save_commit_message(repo_path, message) {
+        Ok(path) => {
+            eprintln!("💾 Saved the commit message to {}", path.display());
+            eprintln!(
+                "   Commit it later with: git commit{} -F {}",
+                if amend { " --amend" } else { "" },
+                path.display()
+            );
+            error.context(format!(
+                "Commit was not created; the message was saved to {}",
+                path.display()
+            ))
+        }
+        Err(save_error) => {
+            eprintln!("⚠️ Could not save the commit message: {:#}", save_error);
+            eprintln!("Message was:\n{}", message);
+            error
+        }
+    }
+}
+
+/// Exits after an interrupted generation.
+fn exit_interrupted() -> ! {
+    eprintln!("❌ Generation cancelled by user.");
+    std::process::exit(SIGINT_EXIT_CODE);
+}
 
-        print!(
-            "🤖 Generating {} {}commit message variations from AI... ",
+/// Starts generating in the background so the menu can open before the
+/// response is complete. Progress is delivered through the returned receiver.
+fn spawn_generation(
+    prompt: &prompt::Prompt,
+    num_candidates: u32,
+    regeneration: u32,
+    config: &config::Config,
+) -> (
+    JoinHandle<anyhow::Result<ai::Generation>>,
+    mpsc::UnboundedReceiver<ai::Progress>,
+) {
+    let (progress_tx, progress_rx) = mpsc::unbounded_channel();
+    let prompt = prompt.clone();
+    let config = config.clone();
+    let task = tokio::spawn(async move {
+        let request = ai::GenerationRequest {
+            prompt: &prompt,
+            num_candidates,
+            regeneration,
+        };
+        ai::generate_text(&request, &config, &|progress| {
+            let _ = progress_tx.send(progress);
+        })
+        .await
+    });
+    (task, progress_rx)
+}
+
+/// Why waiting on a background generation stopped.
+enum Waited {
+    /// A new suggestion was appended to the streamed list.
+    Suggestion,
+    Finished(anyhow::Result<ai::Generation>),
+    Interrupted,
+}
+
+/// Waits for the next streamed suggestion, the end of the generation or
+/// Ctrl-C, keeping the spinner line up to date meanwhile.
+async fn wait_for_generation(
+    task: &mut JoinHandle<anyhow::Result<ai::Generation>>,
+    progress: &mut mpsc::UnboundedReceiver<ai::Progress>,
+    streamed: &mut Vec<String>,
+    spinner_label: &str,
+    tally: &UsageTally,
+) -> Waited {
+    let interrupted = interrupted();
+    tokio::pin!(interrupted);
+    loop {
+        tokio::select! {
+            Some(event) = progress.recv() => match event {
+                ai::Progress::Suggestion(suggestion) => {
+                    streamed.push(suggestion);
+                    return Waited::Suggestion;
+                }
+                status => spinner_status(spinner_label, tally)(status),
+            },
+            result = &mut *task => {
+                return Waited::Finished(result.unwrap_or_else(|e| Err(e.into())));
+            }
+            _ = &mut interrupted => return Waited::Interrupted,
+        }
+    }
+}
+
+/// Cancels a background generation that is still running and reports the
+/// tokens it billed, including those of an answer cut short.
+async fn end_generation(
+    task: &mut JoinHandle<anyhow::Result<ai::Generation>>,
+    progress: &mut mpsc::UnboundedReceiver<ai::Progress>,
+    tally: &UsageTally,
+    repo_path: &Path,
+    config: &config::Config,
+) {
+    if !task.is_finished() {
+        task.abort();
+        // Once the task has stopped, every report it sent is in the channel.
+        let _ = (&mut *task).await;
+    }
+    while let Ok(event) = progress.try_recv() {
+        tally.record(&event);
+    }
+    tally.report(repo_path, config);
+}
+
+async fn interactive_commit_loop(
+    repo_path: &Path,
+    prompt: &prompt::Prompt,
+    num_variations_to_request: u32,
+    mode_description: &str,
+    config: &config::Config,
+    selector: &dyn Selector,
+) -> anyhow::Result<Option<String>> {
+    let mut next_regeneration = 0;
+    'generation: loop {
+        let regeneration = next_regeneration;
+        next_regeneration += 1;
+        let spinner_label = format!(
+            "🤖 Generating {} {}commit message variations from AI...",
             num_variations_to_request,
             if mode_description.is_empty() {
                 "".to_string()
@@ -79,87 +735,497 @@ async fn interactive_commit_loop(
                 format!("{} ", mode_description)
             }
         );
+        print!("{} ", spinner_label);
         io::stdout().flush()?;
-        let suggestions_result = ai::generate_text(&prompt_str, num_variations_to_request).await;
-        println!("\r \r");
-
-        let suggestions = match suggestions_result {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Error generating commit messages from AI: {}", e);
-                let error_options = vec![REGENERATE_OPTION, CANCEL_OPTION];
-                match Select::new("AI failed. What would you like to do?", error_options).prompt() {
-                    Ok(REGENERATE_OPTION) => continue,
-                    Ok(CANCEL_OPTION) | Err(InquireError::OperationCanceled) => return Ok(None),
-                    Ok(_) => unreachable!(),
-                    Err(ie) => return Err(ie.into()),
+        let (mut task, mut progress) =
+            spawn_generation(prompt, num_variations_to_request, regeneration, config);
+        let tally = UsageTally::default();
+        let mut streamed: Vec<String> = Vec::new();
+        let mut waited = wait_for_generation(
+            &mut task,
+            &mut progress,
+            &mut streamed,
+            &spinner_label,
+            &tally,
+        )
+        .await;
+        println!("\r\x1b[2K");
+
+        loop {
+            let mut options = match waited {
+                Waited::Interrupted if streamed.is_empty() => {
+                    end_generation(&mut task, &mut progress, &tally, repo_path, config).await;
+                    eprintln!("⚠️ Generation cancelled.");
+                    if ask_regenerate(selector, "Generation cancelled. What would you like to do?")?
+                    {
+                        continue 'generation;
+                    }
+                    return Ok(None);
                 }
-            }
-        };
+                Waited::Finished(Err(e)) if streamed.is_empty() => {
+                    end_generation(&mut task, &mut progress, &tally, repo_path, config).await;
+                    eprintln!(
+                        "Error generating commit messages from AI: {:#}",
+                        redact::redact(&format!("{:#}", e))
+                    );
+                    if ask_regenerate(selector, "AI failed. What would you like to do?")? {
+                        continue 'generation;
+                    }
+                    return Ok(None);
+                }
+                Waited::Finished(Ok(generation)) => {
+                    end_generation(&mut task, &mut progress, &tally, repo_path, config).await;
+                    if generation.suggestions.is_empty() {
+                        eprintln!("❌ AI returned no valid suggestions after filtering.");
+                        if ask_regenerate(
+                            selector,
+                            "AI returned no suggestions. What would you like to do?",
+                        )? {
+                            continue 'generation;
+                        }
+                        return Ok(None);
+                    }
+                    println!("💡 Suggestions from {}", describe_source(&generation));
+                    generation.suggestions
+                }
+                Waited::Interrupted => {
+                    end_generation(&mut task, &mut progress, &tally, repo_path, config).await;
+                    eprintln!("⚠️ Generation cancelled; showing the suggestions received so far.");
+                    streamed.clone()
+                }
+                Waited::Finished(Err(e)) => {
+                    end_generation(&mut task, &mut progress, &tally, repo_path, config).await;
+                    eprintln!(
+                        "⚠️ Generation failed; showing the suggestions received so far: {:#}",
+                        redact::redact(&format!("{:#}", e))
+                    );
+                    streamed.clone()
+                }
+                Waited::Suggestion => {
+                    while let Ok(event) = progress.try_recv() {
+                        match event {
+                            ai::Progress::Suggestion(suggestion) => streamed.push(suggestion),
+                            event => tally.record(&event),
+                        }
+                    }
+                    println!("💡 First suggestions (more are still arriving)");
+                    let mut options = streamed.clone();
+                    options.push(MORE_SUGGESTIONS_OPTION.to_string());
+                    options
+                }
+            };
+            options.push(REGENERATE_OPTION.to_string());
+            options.push(CANCEL_OPTION.to_string());
 
-        if suggestions.is_empty() {
-            eprintln!("❌ AI returned no valid suggestions after filtering.");
-            let empty_options = vec![REGENERATE_OPTION, CANCEL_OPTION];
-            match Select::new(
-                "AI returned no suggestions. What would you like to do?",
-                empty_options,
-            )
-            .prompt()
-            {
-                Ok(REGENERATE_OPTION) => continue,
-                Ok(CANCEL_OPTION) | Err(InquireError::OperationCanceled) => return Ok(None),
-                Ok(_) => unreachable!(),
-                Err(ie) => return Err(ie.into()),
+            let selection = selector.select("Select a commit message (or action):", options);
+            if matches!(&selection, Ok(item) if item == MORE_SUGGESTIONS_OPTION) {
+                print!("{} ", spinner_label);
+                io::stdout().flush()?;
+                waited = wait_for_generation(
+                    &mut task,
+                    &mut progress,
+                    &mut streamed,
+                    &spinner_label,
+                    &tally,
+                )
+                .await;
+                println!("\r\x1b[2K");
+                continue;
+            }
+            end_generation(&mut task, &mut progress, &tally, repo_path, config).await;
+            match selection {
+                Ok(selected_item) => {
+                    if selected_item == REGENERATE_OPTION {
+                        continue 'generation;
+                    } else if selected_item == CANCEL_OPTION {
+                        println!("❌ Commit process cancelled by user.");
+                        return Ok(None);
+                    } else {
+                        return Ok(Some(selected_item));
+                    }
+                }
+                Err(InquireError::OperationCanceled) => {
+                    println!("❌ Commit message selection cancelled.");
+                    return Ok(None);
+                }
+                Err(e) => {
+                    eprintln!("Error during selection: {}", e);
+                    return Err(e.into());
+                }
             }
         }
+    }
+}
 
-        let mut options: Vec<String> = suggestions.clone();
-        options.push(REGENERATE_OPTION.to_string());
-        options.push(CANCEL_OPTION.to_string());
+fn main() {
+    if let Err(e) = run_main() {
+        eprintln!("Error: {}", redact::redact(&format!("{:?}", e)));
+        std::process::exit(1);
+    }
+}
 
-        match Select::new("Select a commit message (or action):", options).prompt() {
-            Ok(selected_item) => {
-                if selected_item == REGENERATE_OPTION {
-                    continue;
-                } else if selected_item == CANCEL_OPTION {
-                    println!("❌ Commit process cancelled by user.");
-                    return Ok(None);
-                } else {
-                    return Ok(Some(selected_item));
+fn run_main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    if let Some(dir) = &args.directory {
+        env::set_current_dir(dir)
+            .with_context(|| format!("Cannot change to directory {}", dir.display()))?;
+    }
+    let start_dir = env::current_dir().context("Failed to get current directory")?;
+    pin_git_env_paths(&start_dir);
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start the async runtime")?
+        .block_on(async {
+            tokio::spawn(watch_ctrl_c());
+            run_cli(args, &start_dir).await
+        })
+}
+
+/// Makes a relative `GIT_DIR` or `GIT_WORK_TREE` absolute. Git commands run
+/// from the work tree root, where the relative path would point elsewhere.
+fn pin_git_env_paths(start_dir: &Path) {
+    for var in ["GIT_DIR", "GIT_WORK_TREE"] {
+        if let Some(value) = env::var_os(var)
+            && Path::new(&value).is_relative()
+        {
+            // SAFETY: called from `main` before the runtime starts any threads.
+            unsafe { env::set_var(var, start_dir.join(value)) };
+        }
+    }
+}
+
+async fn run_cli(args: Args, start_dir: &Path) -> anyhow::Result<()> {
+    let mode = args.determine_mode();
+    let repo = git::resolve_repo_root(start_dir);
+    // Outside a repository only the global config applies.
+    let config = config::load(repo.as_deref().ok())
+        .context("Failed to load configuration")
+        .and_then(|mut config| {
+            args.apply_overrides(&mut config);
+            config.generation.validate()?;
+            Ok(config)
+        });
+    match &args.command {
+        Some(Command::Eval(eval_args)) => run_eval(eval_args, &repo?, &config?).await,
+        Some(Command::Prompt(prompt_args)) => print_prompt(prompt_args, &repo?, &config?),
+        // Managing keys needs no repository.
+        Some(Command::Auth(auth_command)) => run_auth(auth_command, &config?),
+        Some(Command::Doctor) => run_doctor(start_dir, repo, config).await,
+        Some(Command::Usage(usage_args)) => print_usage(usage_args, &config?),
+        None => run(mode, &repo?, &config?, &TerminalSelector).await,
+    }
+}
+
+/// Runs the diagnostics. Unlike other commands it reports, rather than stops
+/// at, a missing repository or a broken configuration.
+async fn run_doctor(
+    start_dir: &Path,
+    repo: anyhow::Result<PathBuf>,
+    config: anyhow::Result<config::Config>,
+) -> anyhow::Result<()> {
+    let checks = doctor::run_checks(start_dir, repo, config).await;
+    println!("{}", doctor::format_report(&checks));
+    if checks
+        .iter()
+        .any(|check| check.status == doctor::Status::Fail)
+    {
+        bail!("ai-commit doctor found problems.");
+    }
+    Ok(())
+}
+
+fn print_usage(usage_args: &UsageArgs, config: &config::Config) -> anyhow::Result<()> {
+    let Some(path) = usage::log_path(&config.usage) else {
+        bail!("Cannot locate the data directory holding the usage log.");
+    };
+    let mut records = usage::read_log(&path)?;
+    if let Some(days) = usage_args.days {
+        let cutoff = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+            .saturating_sub(days * 86_400);
+        records.retain(|record| record.timestamp >= cutoff);
+    }
+    if records.is_empty() {
+        println!("No usage recorded in {}.", path.display());
+        return Ok(());
+    }
+    let groupings = match usage_args.by {
+        Some(group_by) => vec![group_by],
+        None => usage::GroupBy::ALL.to_vec(),
+    };
+    println!("{}", usage::format_summary(&records, &groupings));
+    Ok(())
+}
+
+/// The provider `auth` subcommands act on when none is given.
+fn default_provider(config: &config::Config) -> config::Provider {
+    config
+        .models
+        .first()
+        .map_or(config::Provider::Gemini, |model| model.provider)
+}
+
+fn run_auth(command: &AuthCommand, config: &config::Config) -> anyhow::Result<()> {
+    match command {
+        AuthCommand::Login { provider, file } => {
+            let provider = provider.unwrap_or_else(|| default_provider(config));
+            let key = read_api_key(provider)?;
+            match auth::store_api_key(provider, &key, *file)? {
+                auth::Stored::Keyring => {
+                    println!(
+                        "🔑 Saved the {} API key in the OS keyring.",
+                        provider.name()
+                    );
+                }
+                auth::Stored::File {
+                    path,
+                    keyring_error,
+                } => {
+                    if let Some(e) = keyring_error {
+                        eprintln!("⚠️ {}", e);
+                    }
+                    println!(
+                        "🔑 Saved the {} API key in {}.",
+                        provider.name(),
+                        path.display()
+                    );
                 }
             }
-            Err(InquireError::OperationCanceled) => {
-                println!("❌ Commit message selection cancelled.");
-                return Ok(None);
+        }
+        AuthCommand::Logout { provider } => {
+            let provider = provider.unwrap_or_else(|| default_provider(config));
+            let removed = auth::delete_api_key(provider)?;
+            if removed.is_empty() {
+                println!("No stored {} API key to remove.", provider.name());
             }
-            Err(e) => {
-                eprintln!("Error during selection: {}", e);
-                return Err(e.into());
+            for source in removed {
+                println!(
+                    "🗑️ Removed the {} API key from the {}.",
+                    provider.name(),
+                    source
+                );
             }
         }
+        AuthCommand::Status => print_auth_status(config),
     }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    let mode = args.determine_mode();
-    let repo_path = env::current_dir().context("Failed to get current directory")?;
+/// Prompts for the key without echoing it, or reads it from stdin when piped.
+fn read_api_key(provider: config::Provider) -> anyhow::Result<String> {
+    use std::io::IsTerminal;
+    let key = if io::stdin().is_terminal() {
+        inquire::Password::new(&format!("{} API key:", provider.name()))
+            .without_confirmation()
+            .prompt()?
+    } else {
+        let mut key = String::new();
+        io::stdin()
+            .read_line(&mut key)
+            .context("Failed to read the API key from stdin")?;
+        key
+    };
+    let key = key.trim();
+    if key.is_empty() {
+        bail!("No API key given.");
+    }
+    Ok(key.to_string())
+}
+
+/// Lists every key source per provider in resolution order, marking the one in use.
+fn print_auth_status(config: &config::Config) {
+    println!("API keys are read from the first of these that has one:");
+    for provider in [config::Provider::Gemini, config::Provider::OpenAi] {
+        println!("\n{}:", provider.name());
+        let mut found = false;
+        for (position, status) in auth::status(provider, config).into_iter().enumerate() {
+            let detail = match (&status.location, &status.key) {
+                (None, _) => "not configured".to_string(),
+                (Some(location), Ok(None)) => format!("{} (no key)", location),
+                (Some(location), Ok(Some(key))) => format!("{} ({})", location, auth::mask(key)),
+                (Some(location), Err(e)) => format!("{} (error: {:#})", location, e),
+            };
+            let marker = if !found && matches!(status.key, Ok(Some(_))) {
+                found = true;
+                "→"
+            } else {
+                " "
+            };
+            println!(
+                "  {} {}. {}: {}",
+                marker,
+                position + 1,
+                status.source,
+                detail
+            );
+        }
+        if !found {
+            println!("  No key found.");
+        }
+    }
+}
+
+/// Loads the project guidelines, warning about any that had to be truncated.
+fn load_guidelines(
+    config: &config::Config,
+    repo_path: &Path,
+) -> anyhow::Result<Option<instructions::ProjectGuidelines>> {
+    let guidelines = instructions::load(&config.instructions, repo_path)?;
+    for path in guidelines.iter().flat_map(|g| &g.truncated) {
+        eprintln!(
+            "⚠️ {} is longer than {} characters and was truncated.",
+            path.display(),
+            config.instructions.max_chars
+        );
+    }
+    Ok(guidelines)
+}
+
+/// Renders the prompt for the staged changes exactly as a commit run would.
+fn print_prompt(
+    prompt_args: &PromptArgs,
+    repo_path: &Path,
+    config: &config::Config,
+) -> anyhow::Result<()> {
+    let template = prompt::PromptTemplate::from_config(&config.prompt, repo_path)?;
+    let backend = git::open_backend(&config.git, repo_path)?;
+    let raw_diff_text = backend.staged_diff()?;
+    let preprocessed_diff_text = if !raw_diff_text.is_empty() {
+        diff::preprocess_diff_for_ai(&raw_diff_text)
+    } else {
+        String::new()
+    };
+    let changes_summary = backend.staged_changes_summary()?;
+    let previous_message = if prompt_args.amend {
+        backend.previous_commit_message()?
+    } else {
+        None
+    };
+    let branch = backend.current_branch().unwrap_or(None);
+    let guidelines = load_guidelines(config, repo_path)?;
+
+    let prompt = template.render(&prompt::PromptInput {
+        diff: &preprocessed_diff_text,
+        changes_summary: &changes_summary,
+        num_suggestions: prompt_args.variations.max(1),
+        previous_message: previous_message.as_deref(),
+        branch: branch.as_deref(),
+        guidelines: guidelines.as_ref().map(|g| g.text.as_str()),
+    })?;
+    let text = prompt.text();
+    eprintln!(
+        "📝 Prompt rendered from {} ({} characters, {} of them fixed instructions):",
+        template.name(),
+        text.chars().count(),
+        prompt.system.chars().count()
+    );
+    for source in guidelines.iter().flat_map(|g| &g.sources) {
+        eprintln!("📋 Including project guidelines from {}", source.display());
+    }
+    println!("{}", text);
+    Ok(())
+}
+
+async fn run_eval(
+    eval_args: &EvalArgs,
+    repo_path: &Path,
+    config: &config::Config,
+) -> anyhow::Result<()> {
+    let cases = match &eval_args.dataset {
+        Some(path) => eval::load_dataset(path)?,
+        None => {
+            let cases = eval::harvest_dataset(repo_path, eval_args.limit)?;
+            println!("📚 Harvested {} case(s) from commit history.", cases.len());
+            if let Some(path) = &eval_args.save_dataset {
+                eval::save_dataset(path, &cases)?;
+                println!("💾 Saved dataset to {}", path.display());
+            }
+            cases
+        }
+    };
+    let baseline = eval_args
+        .baseline
+        .as_deref()
+        .map(eval::load_report)
+        .transpose()?;
+
+    let template = prompt::PromptTemplate::from_config(&config.prompt, repo_path)?;
+    let guidelines = load_guidelines(config, repo_path)?;
+
+    let total = cases.len();
+    let report = eval::run_eval(
+        &cases,
+        &template,
+        guidelines.as_ref().map(|g| g.text.as_str()),
+        config,
+        eval_args.label.clone(),
+        &|index, result| {
+            let outcome = if result.type_correct { "✅" } else { "❌" };
+            println!(
+                "{} [{}/{}] {}: expected {}, got {} ({} ms)",
+                outcome,
+                index + 1,
+                total,
+                result.id,
+                result.expected_type,
+                result.predicted_type,
+                result.latency_ms
+            );
+        },
+    )
+    .await?;
+
+    println!("\n{}", eval::format_report(&report));
+    if let Some(baseline) = &baseline {
+        println!("{}", eval::format_comparison(&report, baseline));
+    }
+    if let Some(path) = &eval_args.output {
+        eval::save_report(path, &report)?;
+        println!("💾 Saved results to {}", path.display());
+    }
+    Ok(())
+}
 
+/// Runs one commit flow in `repo_path`, asking `selector` whenever the user
+/// has to choose between suggestions or actions.
+async fn run(
+    mode: AiCommitMode,
+    repo_path: &Path,
+    config: &config::Config,
+    selector: &dyn Selector,
+) -> anyhow::Result<()> {
+    let repo_path = repo_path.to_path_buf();
+    let template = prompt::PromptTemplate::from_config(&config.prompt, &repo_path)?;
+    let backend = git::open_backend(&config.git, &repo_path)?;
+    let commit_options = git::CommitOptions::from_config(&config.commit, &repo_path)?;
+    let branch = backend.current_branch().unwrap_or(None);
+    let guidelines = load_guidelines(config, &repo_path)?;
+    if config.instructions.show
+        && let Some(guidelines) = &guidelines
+    {
+        println!("📋 Project guidelines:\n{}\n", guidelines.text);
+    }
+    let guidelines_text = guidelines.as_ref().map(|g| g.text.as_str());
     if !matches!(mode, AiCommitMode::Auto | AiCommitMode::Interactive) {
-        if !git::has_staged_files(&repo_path).context("Failed to check for staged files")?
+        if !backend
+            .has_staged_files()
+            .context("Failed to check for staged files")?
             && !matches!(
                 mode,
                 AiCommitMode::AmendAuto | AiCommitMode::AmendInteractive
             )
         {
-            if !args.amend {
-                println!("ℹ️ No files staged for commit. Nothing to do.");
-                return Ok(());
-            }
+            println!("ℹ️ No files staged for commit. Nothing to do.");
+            return Ok(());
         }
     } else {
-        if !git::has_staged_files(&repo_path).context("Failed to check for staged files")? {
+        if !backend
+            .has_staged_files()
+            .context("Failed to check for staged files")?
+        {
             println!("ℹ️ No files staged for commit. Nothing to do.");
             return Ok(());
         }
@@ -167,7 +1233,7 @@ async fn main() -> anyhow::Result<()> {
 
     match mode {
         AiCommitMode::Auto => {
-            let raw_diff_text = match git::get_staged_diff(&repo_path) {
+            let raw_diff_text = match backend.staged_diff() {
                 Ok(diff) if !diff.is_empty() => diff,
                 Ok(_) => {
                     println!(
@@ -187,7 +1253,7 @@ async fn main() -> anyhow::Result<()> {
                 String::new()
             };
 
-            let changes_summary = match git::get_staged_changes_summary(&repo_path) {
+            let changes_summary = match backend.staged_changes_summary() {
                 Ok(summary) => summary,
                 Err(e) => {
                     eprintln!("Error getting staged changes summary: {}", e);
@@ -195,29 +1261,54 @@ async fn main() -> anyhow::Result<()> {
                 }
             };
 
-            let prompt_str =
-                prompt::build_prompt(&preprocessed_diff_text, &changes_summary, 1, None);
-
-            if env::var("AI_COMMIT_LOG_PROMPT").is_ok() {
-                println!("\n================ PROMPT SENT TO AI (AUTO MODE) ================");
-                println!("{}", prompt_str);
-                println!("==============================================================\n");
-            }
+            let prompt = preflight::fit_prompt(config, &preprocessed_diff_text, |diff| {
+                template.render(&prompt::PromptInput {
+                    diff,
+                    changes_summary: &changes_summary,
+                    num_suggestions: 1,
+                    previous_message: None,
+                    branch: branch.as_deref(),
+                    guidelines: guidelines_text,
+                })
+            })
+            .await?;
 
-            print!("🤖 Generating commit message from AI... ");
+            let spinner_label = "🤖 Generating commit message from AI...";
+            print!("{} ", spinner_label);
             io::stdout().flush()?;
-            let suggestions_result = ai::generate_text(&prompt_str, 1).await;
-            println!("\r \r");
+            let request = ai::GenerationRequest {
+                prompt: &prompt,
+                num_candidates: 1,
+                regeneration: 0,
+            };
+            let tally = UsageTally::default();
+            let suggestions_result = until_interrupted(ai::generate_text(
+                &request,
+                config,
+                &spinner_status(spinner_label, &tally),
+            ))
+            .await;
+            println!("\r\x1b[2K");
+            tally.report(&repo_path, config);
+            let suggestions_result = suggestions_result.unwrap_or_else(|| exit_interrupted());
 
-            let suggestions = match suggestions_result {
-                Ok(s) => s,
+            let generation = match suggestions_result {
+                Ok(g) => g,
                 Err(e) => {
-                    eprintln!("Error generating commit message from AI: {}", e);
-                    return Err(e.into());
+                    eprintln!(
+                        "Error generating commit message from AI: {:#}",
+                        redact::redact(&format!("{:#}", e))
+                    );
+                    return Err(e);
                 }
             };
 
-            let commit_message = suggestions.get(0).map(String::as_str).unwrap_or("").trim();
+            let commit_message = generation
+                .suggestions
+                .first()
+                .map(String::as_str)
+                .unwrap_or("")
+                .trim();
             if commit_message.is_empty() {
                 eprintln!(
                     "❌ AI returned an empty or invalid commit message after filtering. Cannot commit."
@@ -226,22 +1317,18 @@ async fn main() -> anyhow::Result<()> {
                     "AI returned an empty or invalid commit message."
                 ));
             }
-            println!("✨ AI Suggests: \"{}\"", commit_message);
-            match git::commit_staged_files(&repo_path, commit_message) {
-                Ok(commit_output) => {
-                    println!("\n✅ Automatically committed with AI-generated message:");
-                    println!("{}", commit_output);
-                }
-                Err(e) => {
-                    eprintln!("\n❌ Failed to commit staged files: {}", e);
-                    eprintln!("Generated message was: \"{}\"", commit_message);
-                    eprintln!("Please commit manually or try again.");
-                    return Err(e);
-                }
-            }
+            println!(
+                "✨ AI Suggests ({}): \"{}\"",
+                describe_source(&generation),
+                commit_message
+            );
+            let commit_output =
+                commit_with_recovery(&repo_path, commit_message, false, &commit_options, None)?;
+            println!("\n✅ Automatically committed with AI-generated message:");
+            println!("{}", commit_output);
         }
         AiCommitMode::Interactive => {
-            let raw_diff_text = match git::get_staged_diff(&repo_path) {
+            let raw_diff_text = match backend.staged_diff() {
                 Ok(diff) if !diff.is_empty() => diff,
                 Ok(_) => {
                     println!(
@@ -261,7 +1348,7 @@ async fn main() -> anyhow::Result<()> {
                 String::new()
             };
 
-            let changes_summary = match git::get_staged_changes_summary(&repo_path) {
+            let changes_summary = match backend.staged_changes_summary() {
                 Ok(summary) => summary,
                 Err(e) => {
                     eprintln!("Error getting staged changes summary: {}", e);
@@ -269,30 +1356,40 @@ async fn main() -> anyhow::Result<()> {
                 }
             };
             let num_variations_to_request = 5;
+            let prompt = template.render(&prompt::PromptInput {
+                diff: &preprocessed_diff_text,
+                changes_summary: &changes_summary,
+                num_suggestions: num_variations_to_request,
+                previous_message: None,
+                branch: branch.as_deref(),
+                guidelines: guidelines_text,
+            })?;
+            if !confirm_prompt_size(&prompt.text(), config, selector).await? {
+                println!("❌ Commit process cancelled by user.");
+                return Ok(());
+            }
 
             match interactive_commit_loop(
                 &repo_path,
-                &preprocessed_diff_text,
-                &changes_summary,
+                &prompt,
                 num_variations_to_request,
-                None,
                 "",
+                config,
+                selector,
             )
             .await
             {
                 Ok(Some(selected_message)) => {
                     println!("✨ You selected: \"{}\"", selected_message);
-                    match git::commit_staged_files(&repo_path, &selected_message) {
-                        Ok(commit_output) => {
-                            println!("\n✅ Committed with selected message:");
-                            println!("{}", commit_output);
-                        }
-                        Err(e) => {
-                            eprintln!("\n❌ Failed to commit staged files: {}", e);
-                            eprintln!("Selected message was: \"{}\"", selected_message);
-                            return Err(e);
-                        }
-                    }
+                    let commit_output = commit_with_recovery(
+                        &repo_path,
+                        &selected_message,
+                        false,
+                        &commit_options,
+                        Some(selector),
+                    )?;
+                    println!("\n✅ Committed with selected message:");
+                    println!("{}", commit_output);
                 }
                 Ok(None) => {}
                 Err(e) => {
@@ -302,7 +1399,8 @@ async fn main() -> anyhow::Result<()> {
             }
         }
         AiCommitMode::AmendAuto | AiCommitMode::AmendInteractive => {
-            let previous_commit_msg = match git::get_previous_commit_message(&repo_path)
+            let previous_commit_msg = match backend
+                .previous_commit_message()
                 .context("Failed to get previous commit message for amend operation")?
             {
                 Some(msg) => msg,
@@ -315,7 +1413,7 @@ async fn main() -> anyhow::Result<()> {
                 previous_commit_msg.lines().next().unwrap_or_default()
             );
 
-            let raw_diff_text = match git::get_staged_diff(&repo_path) {
+            let raw_diff_text = match backend.staged_diff() {
                 Ok(diff) => diff,
                 Err(e) => {
                     eprintln!("Error getting staged diff for amend: {}", e);
@@ -329,7 +1427,7 @@ async fn main() -> anyhow::Result<()> {
                 String::new()
             };
 
-            let changes_summary = match git::get_staged_changes_summary(&repo_path) {
+            let changes_summary = match backend.staged_changes_summary() {
                 Ok(summary) => summary,
                 Err(e) => {
                     eprintln!("Error getting staged changes summary for amend: {}", e);
@@ -338,37 +1436,53 @@ async fn main() -> anyhow::Result<()> {
             };
 
             if mode == AiCommitMode::AmendAuto {
-                let prompt_str = prompt::build_prompt(
-                    &preprocessed_diff_text,
-                    &changes_summary,
-                    1,
-                    Some(&previous_commit_msg),
-                );
-
-                if env::var("AI_COMMIT_LOG_PROMPT").is_ok() {
-                    println!(
-                        "\n================ PROMPT SENT TO AI (AMEND AUTO MODE) ================"
-                    );
-                    println!("{}", prompt_str);
-                    println!(
-                        "====================================================================\n"
-                    );
-                }
+                let prompt = preflight::fit_prompt(config, &preprocessed_diff_text, |diff| {
+                    template.render(&prompt::PromptInput {
+                        diff,
+                        changes_summary: &changes_summary,
+                        num_suggestions: 1,
+                        previous_message: Some(&previous_commit_msg),
+                        branch: branch.as_deref(),
+                        guidelines: guidelines_text,
+                    })
+                })
+                .await?;
 
-                print!("🤖 Generating new commit message for amend (auto)... ");
+                let spinner_label = "🤖 Generating new commit message for amend (auto)...";
+                print!("{} ", spinner_label);
                 io::stdout().flush()?;
-                let suggestions_result = ai::generate_text(&prompt_str, 1).await;
-                println!("\r \r");
+                let request = ai::GenerationRequest {
+                    prompt: &prompt,
+                    num_candidates: 1,
+                    regeneration: 0,
+                };
+                let tally = UsageTally::default();
+                let suggestions_result = until_interrupted(ai::generate_text(
+                    &request,
+                    config,
+                    &spinner_status(spinner_label, &tally),
+                ))
+                .await;
+                println!("\r\x1b[2K");
+                tally.report(&repo_path, config);
+                let suggestions_result = suggestions_result.unwrap_or_else(|| exit_interrupted());
 
-                let suggestions = match suggestions_result {
-                    Ok(s) => s,
+                let generation = match suggestions_result {
+                    Ok(g) => g,
                     Err(e) => {
-                        eprintln!("Error generating commit message from AI for amend: {}", e);
-                        return Err(e.into());
+                        eprintln!(
+                            "Error generating commit message from AI for amend: {:#}",
+                            redact::redact(&format!("{:#}", e))
+                        );
+                        return Err(e);
                     }
                 };
-                let new_commit_message =
-                    suggestions.get(0).map(String::as_str).unwrap_or("").trim();
+                let new_commit_message = generation
+                    .suggestions
+                    .first()
+                    .map(String::as_str)
+                    .unwrap_or("")
+                    .trim();
 
                 if new_commit_message.is_empty() {
                     eprintln!(
@@ -378,43 +1492,55 @@ async fn main() -> anyhow::Result<()> {
                         "AI returned an empty or invalid commit message for amend."
                     ));
                 }
-                println!("✨ AI Suggests for amend: \"{}\"", new_commit_message);
-                match git::amend_commit(&repo_path, new_commit_message) {
-                    Ok(commit_output) => {
-                        println!("\n✅ Successfully amended commit with AI-generated message:");
-                        println!("{}", commit_output);
-                    }
-                    Err(e) => {
-                        eprintln!("\n❌ Failed to amend commit: {}", e);
-                        eprintln!("Generated message was: \"{}\"", new_commit_message);
-                        return Err(e);
-                    }
-                }
+                println!(
+                    "✨ AI Suggests for amend ({}): \"{}\"",
+                    describe_source(&generation),
+                    new_commit_message
+                );
+                let commit_output = commit_with_recovery(
+                    &repo_path,
+                    new_commit_message,
+                    true,
+                    &commit_options,
+                    None,
+                )?;
+                println!("\n✅ Successfully amended commit with AI-generated message:");
+                println!("{}", commit_output);
             } else {
                 let num_variations_to_request = 5;
+                let prompt = template.render(&prompt::PromptInput {
+                    diff: &preprocessed_diff_text,
+                    changes_summary: &changes_summary,
+                    num_suggestions: num_variations_to_request,
+                    previous_message: Some(&previous_commit_msg),
+                    branch: branch.as_deref(),
+                    guidelines: guidelines_text,
+                })?;
+                if !confirm_prompt_size(&prompt.text(), config, selector).await? {
+                    println!("❌ Commit process cancelled by user.");
+                    return Ok(());
+                }
                 match interactive_commit_loop(
                     &repo_path,
-                    &preprocessed_diff_text,
-                    &changes_summary,
+                    &prompt,
                     num_variations_to_request,
-                    Some(&previous_commit_msg),
                     "amend",
+                    config,
+                    selector,
                 )
                 .await
                 {
                     Ok(Some(selected_message)) => {
                         println!("✨ You selected for amend: \"{}\"", selected_message);
-                        match git::amend_commit(&repo_path, &selected_message) {
-                            Ok(commit_output) => {
-                                println!("\n✅ Successfully amended commit with selected message:");
-                                println!("{}", commit_output);
-                            }
-                            Err(e) => {
-                                eprintln!("\n❌ Failed to amend commit: {}", e);
-                                eprintln!("Selected message was: \"{}\"", selected_message);
-                                return Err(e);
-                            }
-                        }
+                        let commit_output = commit_with_recovery(
+                            &repo_path,
+                            &selected_message,
+                            true,
+                            &commit_options,
+                            Some(selector),
+                        )?;
+                        println!("\n✅ Successfully amended commit with selected message:");
+                        println!("{}", commit_output);
                     }
                     Ok(None) => {}
                     Err(e) => {
@@ -427,3 +1553,1180 @@ async fn main() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::fs;
+    use std::process::Command;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use testing::{MockLlmServer, MockResponse};
+
+    enum Answer {
+        Pick(usize),
+        Choose(&'static str),
+        Confirm(bool),
+        Edit(&'static str),
+        Cancel,
+    }
+
+    /// Answers prompts from a script and remembers the options it was shown.
+    struct ScriptedSelector {
+        answers: RefCell<VecDeque<Answer>>,
+        shown: RefCell<Vec<Vec<String>>>,
+    }
+
+    impl ScriptedSelector {
+        fn new(answers: Vec<Answer>) -> Self {
+            ScriptedSelector {
+                answers: RefCell::new(answers.into()),
+                shown: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Selector for ScriptedSelector {
+        fn select(&self, _message: &str, options: Vec<String>) -> Result<String, InquireError> {
+            self.shown.borrow_mut().push(options.clone());
+            match self.answers.borrow_mut().pop_front() {
+                Some(Answer::Pick(index)) => Ok(options[index].clone()),
+                Some(Answer::Choose(option)) => Ok(option.to_string()),
+                Some(_) | None => Err(InquireError::OperationCanceled),
+            }
+        }
+
+        fn confirm(&self, _message: &str) -> Result<bool, InquireError> {
+            match self.answers.borrow_mut().pop_front() {
+                Some(Answer::Confirm(answer)) => Ok(answer),
+                _ => Err(InquireError::OperationCanceled),
+            }
+        }
+
+        fn edit(&self, _message: &str, _text: &str) -> Result<String, InquireError> {
+            match self.answers.borrow_mut().pop_front() {
+                Some(Answer::Edit(text)) => Ok(text.to_string()),
+                _ => Err(InquireError::OperationCanceled),
+            }
+        }
+    }
+
+    fn git(repo_path: &Path, args: &[&str]) -> Result<String> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(repo_path)
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn setup_repo() -> Result<TempDir> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path();
+        git(path, &["init", "-b", "main"])?;
+        git(path, &["config", "user.name", "Test User"])?;
+        git(path, &["config", "user.email", "test@example.com"])?;
+        git(path, &["config", "commit.gpgsign", "false"])?;
+        Ok(temp_dir)
+    }
+
+    fn stage_file(repo_path: &Path, name: &str, content: &str) -> Result<()> {
+        fs::write(repo_path.join(name), content)?;
+        git(repo_path, &["add", name])?;
+        Ok(())
+    }
+
+    fn head_subject(repo_path: &Path) -> Result<String> {
+        git(repo_path, &["log", "-1", "--format=%s"])
+    }
+
+    #[cfg(unix)]
+    fn install_hook(repo_path: &Path, name: &str, script: &str) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let hook = repo_path.join(".git/hooks").join(name);
+        fs::write(&hook, script)?;
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    fn commit_count(repo_path: &Path) -> Result<usize> {
+        Ok(git(repo_path, &["rev-list", "--count", "HEAD"])?.parse()?)
+    }
+
+    #[tokio::test]
+    async fn test_auto_mode_commits_suggestion() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "greeting.rs", "pub fn hello() {}\n")?;
+        let server =
+            MockLlmServer::start(vec![MockResponse::gemini(&["feat: Add greeting helper"])])?;
+        let config = server.config(&["gemini/test-model"]);
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add greeting helper");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert!(
+            requests[0]
+                .path
+                .starts_with("/models/test-model:streamGenerateContent?alt=sse")
+        );
+        assert!(!requests[0].path.contains("key="));
+        assert_eq!(requests[0].header("x-goog-api-key"), Some("mock-api-key"));
+        assert!(requests[0].body.contains("greeting.rs"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auto_mode_retries_after_rate_limit() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "lib.rs", "pub fn parse() {}\n")?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::rate_limited(),
+            MockResponse::gemini(&["feat: Add parser entry point"]),
+        ])?;
+        let config = server.config(&["gemini/test-model"]);
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add parser entry point");
+        assert_eq!(server.requests().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auto_mode_malformed_response_does_not_commit() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        git(repo.path(), &["commit", "-m", "chore: Add initial file"])?;
+        stage_file(repo.path(), "a.txt", "two\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::malformed_json()])?;
+        let config = server.config(&["gemini/test-model"]);
+
+        let result = run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await;
+
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Failed to parse Gemini API response"));
+        assert_eq!(commit_count(repo.path())?, 1);
+        assert_eq!(server.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_prompt_is_not_sent_without_confirmation() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(
+            repo.path(),
+            "generated.rs",
+            &"pub const X: u8 = 0;\n".repeat(200),
+        )?;
+        let server = MockLlmServer::start(vec![])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.preflight.max_prompt_tokens = 500;
+        config.preflight.count_tokens = false;
+
+        run(
+            AiCommitMode::Interactive,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![Answer::Confirm(false)]),
+        )
+        .await?;
+        let result = run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await;
+
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("over the limit of 500"), "{}", error);
+        assert!(server.requests().is_empty());
+        assert!(git::has_staged_files(repo.path())?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocked_prompt_reports_the_reason() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::new(
+            200,
+            r#"{"promptFeedback": {"blockReason": "SAFETY", "safetyRatings": [
+                {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true}
+            ]}}"#,
+        )])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.generation.safety_threshold = Some(config::SafetyThreshold::BlockOnlyHigh);
+
+        let result = run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await;
+
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(
+            error.contains("The model blocked the prompt (SAFETY; flagged: dangerous content)"),
+            "{}",
+            error
+        );
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].body.contains(
+            r#"{"category":"HARM_CATEGORY_DANGEROUS_CONTENT","threshold":"BLOCK_ONLY_HIGH"}"#
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_truncated_response_is_retried_with_more_tokens() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::new(
+                200,
+                r#"{"candidates": [{"content": {"parts": [{"text": "feat: Add"}]}, "finishReason": "MAX_TOKENS"}]}"#,
+            ),
+            MockResponse::gemini(&["feat: Add sample text"]),
+        ])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.generation.max_output_tokens = Some(16);
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add sample text");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].body.contains(r#""maxOutputTokens":16"#));
+        assert!(requests[1].body.contains(r#""maxOutputTokens":32"#));
+        assert!(!requests[0].body.contains("safetySettings"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cut_off_line_is_not_accepted() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        // Cut off mid-sentence, the line still reads like a valid message.
+        let server = MockLlmServer::start(vec![
+            MockResponse::event_stream(&[serde_json::json!({
+                "candidates": [{"index": 0, "content": {"parts": [{"text": "feat: Add sample text file for the"}]}, "finishReason": "MAX_TOKENS"}]
+            })]),
+            MockResponse::gemini(&["feat: Add sample text file"]),
+        ])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.generation.max_output_tokens = Some(16);
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add sample text file");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].body.contains(r#""maxOutputTokens":32"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_openai_cut_off_choice_keeps_only_complete_lines() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::new(
+            200,
+            r#"{"choices": [{"index": 0, "message": {"content": "feat: Add sample text file\nfix: Handle the empty"}, "finish_reason": "length"}]}"#,
+        )])?;
+        let mut config = server.config(&["openai/test-model"]);
+        config.http.stream = false;
+        let selector = ScriptedSelector::new(vec![Answer::Pick(0)]);
+
+        run(AiCommitMode::Interactive, repo.path(), &config, &selector).await?;
+
+        assert_eq!(
+            selector.shown.borrow()[0],
+            vec![
+                "feat: Add sample text file",
+                REGENERATE_OPTION,
+                CANCEL_OPTION
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_errors_do_not_reveal_the_key() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::new(
+            401,
+            r#"{"error": {"message": "API key mock-api-key is not valid"}}"#,
+        )])?;
+        let config = server.config(&["gemini/test-model"]);
+
+        let result = run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await;
+
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(
+            error.contains("API key [REDACTED] is not valid"),
+            "{}",
+            error
+        );
+        assert!(!error.contains("mock-api-key"), "{}", error);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_doctor_reports_missing_model() -> Result<()> {
+        let repo = setup_repo()?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::new(200, r#"{"models": []}"#),
+            MockResponse::new(404, r#"{"error": {"code": 404, "message": "not found"}}"#),
+        ])?;
+        let config = server.config(&["gemini/no-such-model"]);
+
+        let checks =
+            doctor::run_checks(repo.path(), Ok(repo.path().to_path_buf()), Ok(config)).await;
+
+        let status = |name: &str| {
+            checks
+                .iter()
+                .find(|check| check.name == name)
+                .map(|check| check.status)
+        };
+        assert_eq!(status("git"), Some(doctor::Status::Pass));
+        assert_eq!(status("repository"), Some(doctor::Status::Pass));
+        assert_eq!(status("config"), Some(doctor::Status::Pass));
+        assert_eq!(status("gemini API key"), Some(doctor::Status::Pass));
+        assert_eq!(status("gemini connectivity"), Some(doctor::Status::Pass));
+        let model = checks
+            .iter()
+            .find(|check| check.name == "model gemini/no-such-model")
+            .unwrap();
+        assert_eq!(model.status, doctor::Status::Fail);
+        assert!(
+            model.detail.contains("no model named 'no-such-model'"),
+            "{}",
+            model
+        );
+        let requests = server.requests();
+        assert_eq!(requests[1].path, "/models/no-such-model");
+        assert_eq!(requests[1].header("x-goog-api-key"), Some("mock-api-key"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auto_mode_falls_back_to_next_model() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "README.md", "# Project\n")?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::new(400, r#"{"error": {"message": "model not found"}}"#),
+            MockResponse::openai(&["docs: Add project README"]),
+        ])?;
+        let config = server.config(&["gemini/missing-model", "openai/test-model"]);
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "docs: Add project README");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].path, "/chat/completions");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interactive_mode_regenerates_then_commits_selection() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "cache.rs", "pub struct Cache;\n")?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::gemini(&["feat: Add cache type\nfeat: Introduce cache module"]),
+            MockResponse::gemini(&["feat: Add response cache\nrefactor: Extract cache module"]),
+        ])?;
+        let config = server.config(&["gemini/test-model"]);
+        let selector =
+            ScriptedSelector::new(vec![Answer::Choose(REGENERATE_OPTION), Answer::Pick(1)]);
+
+        run(AiCommitMode::Interactive, repo.path(), &config, &selector).await?;
+
+        assert_eq!(head_subject(repo.path())?, "refactor: Extract cache module");
+        assert_eq!(server.requests().len(), 2);
+        let shown = selector.shown.borrow();
+        assert_eq!(
+            shown[0],
+            vec![
+                "feat: Add cache type",
+                "feat: Introduce cache module",
+                REGENERATE_OPTION,
+                CANCEL_OPTION
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interactive_mode_cancel_leaves_changes_staged() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        git(repo.path(), &["commit", "-m", "chore: Add initial file"])?;
+        stage_file(repo.path(), "a.txt", "two\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::gemini(&[
+            "fix: Correct the sample text",
+        ])])?;
+        let config = server.config(&["gemini/test-model"]);
+
+        run(
+            AiCommitMode::Interactive,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![Answer::Choose(CANCEL_OPTION)]),
+        )
+        .await?;
+
+        assert_eq!(commit_count(repo.path())?, 1);
+        assert!(git::has_staged_files(repo.path())?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interactive_mode_error_then_cancel() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::new(
+            401,
+            r#"{"error": {"message": "API key not valid"}}"#,
+        )])?;
+        let config = server.config(&["gemini/test-model"]);
+        let selector = ScriptedSelector::new(vec![Answer::Cancel]);
+
+        run(AiCommitMode::Interactive, repo.path(), &config, &selector).await?;
+
+        assert_eq!(
+            selector.shown.borrow()[0],
+            vec![REGENERATE_OPTION, CANCEL_OPTION]
+        );
+        assert!(git(repo.path(), &["rev-parse", "HEAD"]).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_amend_auto_rewrites_previous_message() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        git(repo.path(), &["commit", "-m", "wip"])?;
+        stage_file(repo.path(), "b.txt", "two\n")?;
+        let server =
+            MockLlmServer::start(vec![MockResponse::openai(&["feat: Add sample text files"])])?;
+        let config = server.config(&["openai/test-model"]);
+
+        run(
+            AiCommitMode::AmendAuto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add sample text files");
+        assert_eq!(commit_count(repo.path())?, 1);
+        assert_eq!(
+            git(repo.path(), &["show", "--format=", "--name-only", "HEAD"])?,
+            "a.txt\nb.txt"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_amend_interactive_includes_previous_message() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        git(
+            repo.path(),
+            &["commit", "-m", "chore: Add placeholder text"],
+        )?;
+        let server = MockLlmServer::start(vec![MockResponse::gemini(&[
+            "docs: Add placeholder text file",
+        ])])?;
+        let config = server.config(&["gemini/test-model"]);
+
+        run(
+            AiCommitMode::AmendInteractive,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![Answer::Pick(0)]),
+        )
+        .await?;
+
+        assert_eq!(
+            head_subject(repo.path())?,
+            "docs: Add placeholder text file"
+        );
+        assert!(
+            server.requests()[0]
+                .body
+                .contains("chore: Add placeholder text")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auto_mode_replays_recorded_fixtures() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "README.md", "## Usage\n")?;
+        let fixtures =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/gemini-rate-limited");
+        let server = MockLlmServer::from_fixtures(&fixtures)?;
+        let config = server.config(&["gemini/test-model"]);
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(
+            head_subject(repo.path())?,
+            "docs: Add usage section to the README"
+        );
+        assert_eq!(server.requests().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_configured_template_is_sent_to_model() -> Result<()> {
+        let repo = setup_repo()?;
+        git(repo.path(), &["checkout", "-b", "feature/greeting"])?;
+        stage_file(repo.path(), "greeting.rs", "pub fn hello() {}\n")?;
+        fs::write(
+            repo.path().join("prompt.jinja"),
+            "Branch {{ branch }}, {{ num_suggestions }} message(s):\n{{ diff }}",
+        )?;
+        let server =
+            MockLlmServer::start(vec![MockResponse::gemini(&["feat: Add greeting helper"])])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.prompt.template = Some(PathBuf::from("prompt.jinja"));
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        let body: serde_json::Value = serde_json::from_str(&server.requests()[0].body)?;
+        let sent_prompt = body["contents"][0]["parts"][0]["text"].as_str().unwrap();
+        assert!(sent_prompt.starts_with("Branch feature/greeting, 1 message(s):\ndiff --git"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_project_guidelines_are_sent_to_model() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "billing.rs", "pub struct Tenant;\n")?;
+        fs::create_dir_all(repo.path().join(config::REPO_CONFIG_DIR))?;
+        fs::write(
+            instructions::repo_instructions_path(repo.path()),
+            "Say 'tenant', never 'customer'.\n",
+        )?;
+        let server = MockLlmServer::start(vec![MockResponse::gemini(&["feat: Add tenant type"])])?;
+        let config = server.config(&["gemini/test-model"]);
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        let body: serde_json::Value = serde_json::from_str(&server.requests()[0].body)?;
+        let instructions = body["systemInstruction"]["parts"][0]["text"]
+            .as_str()
+            .unwrap();
+        assert!(instructions.contains("Project guidelines"));
+        assert!(instructions.contains("Say 'tenant', never 'customer'."));
+        assert_eq!(head_subject(repo.path())?, "feat: Add tenant type");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fixed_instructions_are_sent_as_system_instruction() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "greeting.rs", "pub fn hello() {}\n")?;
+        let server =
+            MockLlmServer::start(vec![MockResponse::gemini(&["feat: Add greeting helper"])])?;
+        let config = server.config(&["gemini/test-model"]);
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        let body: serde_json::Value = serde_json::from_str(&server.requests()[0].body)?;
+        let instructions = body["systemInstruction"]["parts"][0]["text"]
+            .as_str()
+            .unwrap();
+        let request = body["contents"][0]["parts"][0]["text"].as_str().unwrap();
+        assert!(instructions.contains("Available <type>s"));
+        assert!(!instructions.contains("greeting.rs"));
+        assert!(request.contains("greeting.rs"));
+        assert!(!request.contains("Available <type>s"));
+        assert!(body.get("cachedContent").is_none());
+        Ok(())
+    }
+
+    /// Project guidelines long enough to lift the instructions over Gemini's
+    /// minimum size for context caching.
+    fn write_long_guidelines(repo_path: &Path) -> Result<()> {
+        fs::create_dir_all(repo_path.join(config::REPO_CONFIG_DIR))?;
+        fs::write(
+            instructions::repo_instructions_path(repo_path),
+            "Name the affected crate in every subject line.\n".repeat(80),
+        )?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_context_cache_is_created_once_and_reused() -> Result<()> {
+        let repo = setup_repo()?;
+        write_long_guidelines(repo.path())?;
+        let contexts = TempDir::new()?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::new(200, r#"{"totalTokens": 1500}"#),
+            MockResponse::new(200, r#"{"name": "cachedContents/abc123"}"#),
+            MockResponse::gemini(&["feat: Add first file"]),
+            MockResponse::gemini(&["feat: Add second file"]),
+        ])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.context_cache.enabled = true;
+        config.context_cache.dir = Some(contexts.path().to_path_buf());
+
+        for name in ["first.txt", "second.txt"] {
+            stage_file(repo.path(), name, "content\n")?;
+            run(
+                AiCommitMode::Auto,
+                repo.path(),
+                &config,
+                &ScriptedSelector::new(vec![]),
+            )
+            .await?;
+        }
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add second file");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].path, "/models/test-model:countTokens");
+        assert_eq!(requests[1].path, "/cachedContents");
+        let created: serde_json::Value = serde_json::from_str(&requests[1].body)?;
+        assert_eq!(created["model"], "models/test-model");
+        assert_eq!(created["ttl"], "3600s");
+        assert!(
+            created["systemInstruction"]["parts"][0]["text"]
+                .as_str()
+                .unwrap()
+                .contains("Available <type>s")
+        );
+        for request in &requests[2..] {
+            let body: serde_json::Value = serde_json::from_str(&request.body)?;
+            assert_eq!(body["cachedContent"], "cachedContents/abc123");
+            assert!(body.get("systemInstruction").is_none());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_instructions_under_the_cache_minimum_are_sent_in_full() -> Result<()> {
+        let repo = setup_repo()?;
+        let contexts = TempDir::new()?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::new(200, r#"{"totalTokens": 800}"#),
+            MockResponse::gemini(&["feat: Add first file"]),
+            MockResponse::gemini(&["feat: Add second file"]),
+        ])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.context_cache.enabled = true;
+        config.context_cache.dir = Some(contexts.path().to_path_buf());
+
+        for name in ["first.txt", "second.txt"] {
+            stage_file(repo.path(), name, "content\n")?;
+            run(
+                AiCommitMode::Auto,
+                repo.path(),
+                &config,
+                &ScriptedSelector::new(vec![]),
+            )
+            .await?;
+        }
+
+        // The count is remembered, and no cache is ever created.
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].path, "/models/test-model:countTokens");
+        for request in &requests[1..] {
+            let body: serde_json::Value = serde_json::from_str(&request.body)?;
+            assert!(body.get("cachedContent").is_none());
+            assert!(body["systemInstruction"].is_object());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_context_cache_sends_instructions_in_full() -> Result<()> {
+        let repo = setup_repo()?;
+        write_long_guidelines(repo.path())?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let contexts = TempDir::new()?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::new(200, r#"{"totalTokens": 1500}"#),
+            MockResponse::new(
+                400,
+                r#"{"error": {"code": 400, "message": "Cached content is too small.", "status": "INVALID_ARGUMENT"}}"#,
+            ),
+            MockResponse::gemini(&["feat: Add sample text"]),
+        ])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.context_cache.enabled = true;
+        config.context_cache.dir = Some(contexts.path().to_path_buf());
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add sample text");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        let body: serde_json::Value = serde_json::from_str(&requests[2].body)?;
+        assert!(body.get("cachedContent").is_none());
+        assert!(body["systemInstruction"].is_object());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rejected_commit_saves_message() -> Result<()> {
+        let repo = setup_repo()?;
+        install_hook(
+            repo.path(),
+            "pre-commit",
+            "#!/bin/sh\necho 'lint failed' >&2\nexit 1\n",
+        )?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::gemini(&["feat: Add sample"])])?;
+        let config = server.config(&["gemini/test-model"]);
+        let selector = ScriptedSelector::new(vec![
+            Answer::Pick(0),
+            Answer::Choose(NO_VERIFY_OPTION),
+            Answer::Confirm(false),
+            Answer::Choose(SAVE_MESSAGE_OPTION),
+        ]);
+
+        let err = run(AiCommitMode::Interactive, repo.path(), &config, &selector)
+            .await
+            .unwrap_err();
+
+        assert!(format!("{:#}", err).contains("lint failed"));
+        let shown = selector.shown.borrow();
+        assert_eq!(shown.len(), 3);
+        assert_eq!(
+            shown[1],
+            vec![
+                RETRY_COMMIT_OPTION,
+                EDIT_MESSAGE_OPTION,
+                NO_VERIFY_OPTION,
+                SAVE_MESSAGE_OPTION
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(repo.path().join(".git/AI_COMMIT_MSG"))?,
+            "feat: Add sample\n"
+        );
+        assert!(git(repo.path(), &["rev-parse", "HEAD"]).is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_auto_mode_rejected_commit_skips_recovery_menu() -> Result<()> {
+        let repo = setup_repo()?;
+        install_hook(
+            repo.path(),
+            "pre-commit",
+            "#!/bin/sh\necho 'lint failed' >&2\nexit 1\n",
+        )?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::gemini(&["feat: Add sample"])])?;
+        let config = server.config(&["gemini/test-model"]);
+        let selector = ScriptedSelector::new(Vec::new());
+
+        let err = run(AiCommitMode::Auto, repo.path(), &config, &selector)
+            .await
+            .unwrap_err();
+
+        let message = format!("{:#}", err);
+        assert!(message.contains("the message was saved to"), "{}", message);
+        assert!(message.contains("lint failed"), "{}", message);
+        assert!(selector.shown.borrow().is_empty());
+        assert_eq!(
+            fs::read_to_string(repo.path().join(".git/AI_COMMIT_MSG"))?,
+            "feat: Add sample\n"
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rejected_commit_with_no_verify() -> Result<()> {
+        let repo = setup_repo()?;
+        install_hook(repo.path(), "pre-commit", "#!/bin/sh\nexit 1\n")?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::gemini(&["feat: Add sample"])])?;
+        let config = server.config(&["gemini/test-model"]);
+        let selector = ScriptedSelector::new(vec![
+            Answer::Pick(0),
+            Answer::Choose(NO_VERIFY_OPTION),
+            Answer::Confirm(true),
+        ]);
+
+        run(AiCommitMode::Interactive, repo.path(), &config, &selector).await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add sample");
+        assert!(!repo.path().join(".git/AI_COMMIT_MSG").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rejected_commit_retries_with_edited_message() -> Result<()> {
+        let repo = setup_repo()?;
+        install_hook(
+            repo.path(),
+            "commit-msg",
+            "#!/bin/sh\ngrep -q 'Refs: ' \"$1\" || { echo 'missing Refs trailer'; exit 1; }\n",
+        )?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::gemini(&["feat: Add sample"])])?;
+        let config = server.config(&["gemini/test-model"]);
+        let selector = ScriptedSelector::new(vec![
+            Answer::Pick(0),
+            Answer::Choose(RETRY_COMMIT_OPTION),
+            Answer::Choose(EDIT_MESSAGE_OPTION),
+            Answer::Edit("feat: Add sample\n\nRefs: #12\n"),
+        ]);
+
+        run(AiCommitMode::Interactive, repo.path(), &config, &selector).await?;
+
+        assert_eq!(selector.shown.borrow().len(), 3);
+        assert_eq!(
+            git(repo.path(), &["log", "-1", "--format=%B"])?,
+            "feat: Add sample\n\nRefs: #12"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interactive_menu_opens_at_first_streamed_suggestion() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "cache.rs", "pub struct Cache;\n")?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::gemini_stream(&[
+                "feat: Add response cache",
+                "fix: Handle empty diff output",
+            ])
+            .with_event_delay(Duration::from_millis(300)),
+        ])?;
+        let config = server.config(&["gemini/test-model"]);
+        let selector = ScriptedSelector::new(vec![
+            Answer::Choose(MORE_SUGGESTIONS_OPTION),
+            Answer::Pick(1),
+        ]);
+
+        run(AiCommitMode::Interactive, repo.path(), &config, &selector).await?;
+
+        let shown = selector.shown.borrow();
+        assert_eq!(
+            shown[0],
+            vec![
+                "feat: Add response cache",
+                MORE_SUGGESTIONS_OPTION,
+                REGENERATE_OPTION,
+                CANCEL_OPTION
+            ]
+        );
+        assert_eq!(
+            shown[1][..2],
+            ["feat: Add response cache", "fix: Handle empty diff output"]
+        );
+        assert_eq!(head_subject(repo.path())?, "fix: Handle empty diff output");
+        assert!(
+            server.requests()[0]
+                .path
+                .starts_with("/models/test-model:streamGenerateContent?alt=sse")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auto_mode_streams_openai_response() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::openai_stream(&[
+            "Sure, here you go:\nfeat: Add sample text file",
+        ])])?;
+        let config = server.config(&["openai/test-model"]);
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add sample text file");
+        let body: serde_json::Value = serde_json::from_str(&server.requests()[0].body)?;
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["role"], "user");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_usage_is_logged_with_estimated_cost() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![MockResponse::event_stream(&[
+            serde_json::json!({
+                "candidates": [{"index": 0, "content": {"parts": [{"text": "feat: Add sample"}]}}],
+                "usageMetadata": {"promptTokenCount": 100, "candidatesTokenCount": 3}
+            }),
+            serde_json::json!({
+                "candidates": [{"index": 0, "content": {"parts": [{"text": " text file"}]}, "finishReason": "STOP"}],
+                "usageMetadata": {"promptTokenCount": 100, "candidatesTokenCount": 8, "thoughtsTokenCount": 4}
+            }),
+        ])])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        let log_dir = TempDir::new()?;
+        config.usage.log = true;
+        config.usage.path = Some(log_dir.path().join("usage.jsonl"));
+        config.pricing.insert(
+            "gemini/test-model".to_string(),
+            config::ModelPrice {
+                input_per_million: 1.0,
+                output_per_million: 10.0,
+            },
+        );
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        let records = usage::read_log(&log_dir.path().join("usage.jsonl"))?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].model, "gemini/test-model");
+        assert_eq!(records[0].repo, repo.path().display().to_string());
+        assert_eq!(
+            (records[0].input_tokens, records[0].output_tokens),
+            (100, 12)
+        );
+        assert!((records[0].cost_usd.unwrap() - 0.00022).abs() < 1e-12);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_usage_of_failed_models_is_logged() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let rejected = || {
+            MockResponse::event_stream(&[serde_json::json!({
+                "candidates": [{"index": 0, "content": {"parts": [{"text": "banana: Peel the fruit"}]}, "finishReason": "STOP"}],
+                "usageMetadata": {"promptTokenCount": 100, "candidatesTokenCount": 5}
+            })])
+        };
+        let server = MockLlmServer::start(vec![
+            rejected(),
+            rejected(),
+            MockResponse::openai(&["feat: Add sample text file"]),
+        ])?;
+        let mut config = server.config(&["gemini/test-model", "openai/test-model"]);
+        let log_dir = TempDir::new()?;
+        config.usage.log = true;
+        config.usage.path = Some(log_dir.path().join("usage.jsonl"));
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add sample text file");
+        let records = usage::read_log(&log_dir.path().join("usage.jsonl"))?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].model, "gemini/test-model");
+        assert_eq!(
+            (records[0].input_tokens, records[0].output_tokens),
+            (200, 10)
+        );
+        assert_eq!(records[1].model, "openai/test-model");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_usage_of_an_unfinished_stream_is_logged() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::event_stream(&[
+                serde_json::json!({
+                    "candidates": [{"index": 0, "content": {"parts": [{"text": "feat: Add sample text file\n"}]}}],
+                    "usageMetadata": {"promptTokenCount": 100, "candidatesTokenCount": 7}
+                }),
+                serde_json::json!({
+                    "candidates": [{"index": 0, "content": {"parts": [{"text": "docs: Add sample"}]}, "finishReason": "STOP"}],
+                    "usageMetadata": {"promptTokenCount": 100, "candidatesTokenCount": 12}
+                }),
+            ])
+            .with_event_delay(Duration::from_millis(300)),
+        ])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        let log_dir = TempDir::new()?;
+        config.usage.log = true;
+        config.usage.path = Some(log_dir.path().join("usage.jsonl"));
+        let selector = ScriptedSelector::new(vec![Answer::Pick(0)]);
+
+        run(AiCommitMode::Interactive, repo.path(), &config, &selector).await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add sample text file");
+        let records = usage::read_log(&log_dir.path().join("usage.jsonl"))?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            (records[0].input_tokens, records[0].output_tokens),
+            (100, 7)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_stream_uses_generate_content() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "a.txt", "one\n")?;
+        let server =
+            MockLlmServer::start(vec![MockResponse::gemini(&["feat: Add sample text file"])])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.http.stream = false;
+
+        run(
+            AiCommitMode::Auto,
+            repo.path(),
+            &config,
+            &ScriptedSelector::new(vec![]),
+        )
+        .await?;
+
+        assert_eq!(head_subject(repo.path())?, "feat: Add sample text file");
+        assert_eq!(
+            server.requests()[0].path,
+            "/models/test-model:generateContent"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_raises_temperature() -> Result<()> {
+        let repo = setup_repo()?;
+        stage_file(repo.path(), "cache.rs", "pub struct Cache;\n")?;
+        let server = MockLlmServer::start(vec![
+            MockResponse::gemini(&["feat: Add cache type"]),
+            MockResponse::gemini(&["feat: Add response cache"]),
+        ])?;
+        let mut config = server.config(&["gemini/test-model"]);
+        config.generation.temperature = Some(0.5);
+        config.generation.top_k = Some(20);
+        config.generation.regenerate_temperature_step = Some(0.25);
+        let selector =
+            ScriptedSelector::new(vec![Answer::Choose(REGENERATE_OPTION), Answer::Pick(0)]);
+
+        run(AiCommitMode::Interactive, repo.path(), &config, &selector).await?;
+
+        let temperatures: Vec<serde_json::Value> = server
+            .requests()
+            .iter()
+            .map(|request| {
+                let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+                assert_eq!(body["generation_config"]["topK"], 20);
+                body["generation_config"]["temperature"].clone()
+            })
+            .collect();
+        assert_eq!(temperatures, vec![0.5, 0.75]);
+        assert_eq!(head_subject(repo.path())?, "feat: Add response cache");
+        Ok(())
+    }
+}
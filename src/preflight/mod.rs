@@ -0,0 +1,180 @@
+//! Size check run on a rendered prompt before it is sent, so a diff of a
+//! large generated file does not go out unnoticed.
+
+use crate::ai;
+use crate::config::{Config, OversizeAction};
+use crate::diff;
+use crate::prompt::Prompt;
+use crate::usage::thousands;
+use anyhow::{Result, bail};
+
+/// How many times auto mode shrinks the diff before giving up.
+const MAX_TRUNCATIONS: u32 = 3;
+/// Aim a little under the limit, since the estimate is approximate.
+const TRUNCATION_MARGIN: f64 = 0.9;
+
+/// Approximates the token count of `text` at four characters per token.
+pub fn estimate_locally(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+/// Estimates the tokens `prompt` will use. The provider is asked for an exact
+/// count only when the local estimate is within a factor of two of the limit;
+/// if that request fails, the local estimate is used.
+pub async fn estimate(config: &Config, prompt: &str) -> u64 {
+    let local = estimate_locally(prompt);
+    let limit = config.preflight.max_prompt_tokens;
+    if !config.preflight.count_tokens || local < limit / 2 {
+        return local;
+    }
+    let Some(model) = config.models.first() else {
+        return local;
+    };
+    match ai::count_tokens(config, model, prompt).await {
+        Ok(Some(tokens)) => tokens,
+        Ok(None) => local,
+        Err(e) => {
+            eprintln!(
+                "⚠️ Could not count prompt tokens with {}; using an estimate: {:#}",
+                model, e
+            );
+            local
+        }
+    }
+}
+
+/// Returns the estimated size of `prompt` when it is over
+/// `preflight.max_prompt_tokens`.
+pub async fn oversize(config: &Config, prompt: &str) -> Option<u64> {
+    let limit = config.preflight.max_prompt_tokens;
+    if limit == 0 {
+        return None;
+    }
+    let tokens = estimate(config, prompt).await;
+    (tokens > limit).then_some(tokens)
+}
+
+/// Describes an oversized prompt for warnings and errors.
+pub fn describe_oversize(config: &Config, tokens: u64) -> String {
+    format!(
+        "The prompt is about {} tokens, over the limit of {} (preflight.max_prompt_tokens)",
+        thousands(tokens),
+        thousands(config.preflight.max_prompt_tokens)
+    )
+}
+
+/// Renders the prompt for auto mode. When it is over the limit this either
+/// fails or, with `auto_action = "truncate"`, cuts `diff` down and renders
+/// again until the prompt fits.
+pub async fn fit_prompt(
+    config: &Config,
+    diff: &str,
+    render: impl Fn(&str) -> Result<Prompt>,
+) -> Result<Prompt> {
+    let prompt = render(diff)?;
+    let Some(mut tokens) = oversize(config, &prompt.text()).await else {
+        return Ok(prompt);
+    };
+    if config.preflight.auto_action == OversizeAction::Fail {
+        bail!(
+            "{}. Unstage large generated files, raise the limit, or set preflight.auto_action = \"truncate\".",
+            describe_oversize(config, tokens)
+        );
+    }
+
+    let limit = config.preflight.max_prompt_tokens;
+    let mut max_chars = diff.chars().count();
+    for _ in 0..MAX_TRUNCATIONS {
+        max_chars = (max_chars as f64 * limit as f64 / tokens as f64 * TRUNCATION_MARGIN) as usize;
+        let prompt = render(&diff::truncate_diff(diff, max_chars))?;
+        match oversize(config, &prompt.text()).await {
+            None => {
+                eprintln!(
+                    "✂️ Truncated the diff to {} characters to keep the prompt under {} tokens.",
+                    thousands(max_chars as u64),
+                    thousands(limit)
+                );
+                return Ok(prompt);
+            }
+            Some(remaining) => tokens = remaining,
+        }
+    }
+    bail!(
+        "{}, even after truncating the diff. Unstage some changes or raise the limit.",
+        describe_oversize(config, tokens)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockLlmServer, MockResponse};
+
+    fn offline_config(max_prompt_tokens: u64, auto_action: OversizeAction) -> Config {
+        let mut config = Config::default();
+        config.preflight.max_prompt_tokens = max_prompt_tokens;
+        config.preflight.count_tokens = false;
+        config.preflight.auto_action = auto_action;
+        config
+    }
+
+    #[test]
+    fn test_estimate_locally() {
+        assert_eq!(estimate_locally(""), 0);
+        assert_eq!(estimate_locally("abcd"), 1);
+        assert_eq!(estimate_locally("abcde"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fit_prompt_fails_or_truncates() -> Result<()> {
+        let diff = "+line of generated code\n".repeat(1000);
+        let render = |diff: &str| {
+            Ok(Prompt::from(
+                format!("Describe this change:\n{}", diff).as_str(),
+            ))
+        };
+
+        let config = offline_config(0, OversizeAction::Fail);
+        assert!(fit_prompt(&config, &diff, render).await?.request.len() > diff.len());
+
+        let config = offline_config(500, OversizeAction::Fail);
+        let message = format!(
+            "{:#}",
+            fit_prompt(&config, &diff, render).await.unwrap_err()
+        );
+        assert!(message.contains("over the limit of 500"), "{}", message);
+
+        let config = offline_config(500, OversizeAction::Truncate);
+        let prompt = fit_prompt(&config, &diff, render).await?.request;
+        assert!(estimate_locally(&prompt) <= 500);
+        assert!(prompt.starts_with("Describe this change:\n+line of generated code\n"));
+        assert!(prompt.ends_with("more lines omitted ...]"));
+
+        let config = offline_config(3, OversizeAction::Truncate);
+        assert!(fit_prompt(&config, &diff, render).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_estimate_asks_the_provider_near_the_limit() -> Result<()> {
+        let server =
+            MockLlmServer::start(vec![MockResponse::new(200, r#"{"totalTokens": 1234}"#)])?;
+        let mut config = server.config(&["test-model"]);
+        config.preflight.max_prompt_tokens = 10;
+
+        assert_eq!(
+            estimate(&config, "a prompt of about ten tokens...").await,
+            1234
+        );
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/models/test-model:countTokens");
+        assert!(requests[0].body.contains("a prompt of about ten tokens"));
+
+        config.preflight.max_prompt_tokens = 1000;
+        assert_eq!(estimate(&config, "a short prompt").await, 4);
+        assert_eq!(server.requests().len(), 1);
+
+        Ok(())
+    }
+}
@@ -1,13 +1,25 @@
-use crate::git::StagedChangesSummary;
+use crate::config::PromptConfig;
+use crate::git::{FileChange, FileKind, StagedChangesSummary};
+use crate::validate::Rejection;
+use anyhow::{Context, Result};
+use minijinja::{AutoEscape, Environment, ErrorKind, UndefinedBehavior};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
 
-const MIN_COMMIT_DESCRIPTION_CHARS: usize = 10;
-const MAX_COMMIT_DESCRIPTION_CHARS: usize = 72;
+pub const MIN_COMMIT_DESCRIPTION_CHARS: usize = 10;
+pub const MAX_COMMIT_DESCRIPTION_CHARS: usize = 72;
+const DEFAULT_TEMPLATE: &str = include_str!("default_prompt.jinja");
+/// The template block holding the instructions that stay the same from one
+/// run to the next.
+const SYSTEM_BLOCK: &str = "system";
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize)]
 struct CommitType<'a> {
     name: &'a str,
     description: &'a str,
     example: &'a str,
+    #[serde(skip)]
     priority: u8,
 }
 
@@ -86,11 +98,18 @@ const COMMIT_TYPES: &[CommitType] = &[
     },
 ];
 
+pub fn is_known_commit_type(name: &str) -> bool {
+    COMMIT_TYPES.iter().any(|ct| ct.name == name)
+}
+
+fn sort_commit_types(commit_types: &mut [CommitType]) {
+    commit_types.sort_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.name.cmp(b.name)));
+}
+
 fn format_commit_types_for_prompt() -> String {
     let mut s = String::new();
     let mut sorted_commit_types: Vec<CommitType> = COMMIT_TYPES.to_vec();
-    sorted_commit_types
-        .sort_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.name.cmp(b.name)));
+    sort_commit_types(&mut sorted_commit_types);
     for ct in sorted_commit_types {
         s.push_str(&format!(
             "- {}: {} (Example: \"{}\")\n",
@@ -101,8 +120,7 @@ fn format_commit_types_for_prompt() -> String {
 }
 
 fn build_type_selection_guidance() -> String {
-    format!(
-        "CRITICAL: Type Selection Hierarchy and Guidance - When determining the commit type, strictly follow this decision process in order:\n\
+    "CRITICAL: Type Selection Hierarchy and Guidance - When determining the commit type, strictly follow this decision process in order:\n\
          1. 'feat': New functionality, features, or initial project setup.\n\
          2. 'fix': Bug fixes, error corrections, or security vulnerability patches.\n\
          3. 'perf': Performance improvements without new features or bug fixes.\n\
@@ -126,8 +144,7 @@ fn build_type_selection_guidance() -> String {
          - Adding explanatory comments to test utility functions is 'docs', NOT 'test'.\n\
          - A bug fix that also includes adding a regression test is 'fix'.\n\
          - A feature implementation that also includes tests for the new feature is 'feat'.\n\
-         - Refactoring production code and updating its corresponding tests to match the new structure is 'refactor'."
-    )
+         - Refactoring production code and updating its corresponding tests to match the new structure is 'refactor'.".to_string()
 }
 
 fn build_diff_reading_guide() -> String {
@@ -143,120 +160,291 @@ fn build_diff_reading_guide() -> String {
     Pay close attention to whether the content of these marked lines are code, comments, or whitespace to help select the correct commit <type>.".to_string()
 }
 
-pub fn build_prompt(
-    diff_content: &str,
-    changes_summary: &StagedChangesSummary,
-    num_suggestions: u32,
-    previous_message: Option<&str>,
-) -> String {
-    let commit_types_formatted = format_commit_types_for_prompt();
-    let type_selection_guidance = build_type_selection_guidance();
-    let diff_reading_guide = build_diff_reading_guide();
-
-    let binary_changes_summary_str = if changes_summary.binary_file_changes.is_empty() {
-        "No binary file changes detected.".to_string()
-    } else {
-        changes_summary.binary_file_changes.join("\n")
-    };
-    let folder_structure_changes_summary_str = if changes_summary.structure_changes.is_empty() {
-        "No folder structure changes detected.".to_string()
-    } else {
-        changes_summary.structure_changes.join("\n")
-    };
-
-    let mut prompt_parts: Vec<String> = Vec::new();
-
-    if num_suggestions == 1 {
-        prompt_parts.push(format!(
-            "Analyze the following code changes and repository structure modifications. Generate 1 Git commit message."
-        ));
-    } else {
-        prompt_parts.push(format!(
-            "Analyze the following code changes and repository structure modifications. \
-            Your task is to generate {} *alternative* Git commit messages. \
-            Each of these {} messages must be a complete and valid commit message that summarizes *all* the changes provided below. \
-            They should represent different ways of phrasing a *single* commit for the *entirety* of these changes, offering variations in wording or emphasis, but all pertaining to the same overall update. \
-            Do not generate messages for individual files or sub-tasks within the diff if they are part of the same logical change. \
-            \n\nIMPORTANT FOR MULTIPLE VARIATIONS: All {} variations should use the SAME commit type (the most appropriate one for the entire changeset). \
-            Only vary the description part to provide different phrasings of the same conceptual change.",
-            num_suggestions, num_suggestions, num_suggestions
+/// Renders the staged files as a compact table: status code, path, line
+/// counts and anything notable about the file. Empty when nothing is staged.
+pub fn format_file_table(files: &[FileChange]) -> String {
+    if files.is_empty() {
+        return String::new();
+    }
+    let rows: Vec<[String; 4]> = files
+        .iter()
+        .map(|file| {
+            let path = match &file.old_path {
+                Some(old_path) => format!("{} -> {}", old_path, file.path),
+                None => file.path.clone(),
+            };
+            let lines = match (file.added, file.removed) {
+                _ if file.kind == FileKind::Submodule => "-".to_string(),
+                (Some(added), Some(removed)) => format!("+{} -{}", added, removed),
+                _ => "binary".to_string(),
+            };
+            [
+                file.status.code().to_string(),
+                path,
+                lines,
+                file_notes(file).join(", "),
+            ]
+        })
+        .collect();
+
+    let header = ["St", "Path", "Lines", "Notes"].map(str::to_string);
+    let mut widths = header.clone().map(|cell| cell.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            let line: Vec<String> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            line.join("  ").trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn file_notes(file: &FileChange) -> Vec<String> {
+    let mut notes = Vec::new();
+    if let Some(submodule) = &file.submodule {
+        let short = |sha: &Option<String>| match sha {
+            Some(sha) => sha.chars().take(7).collect(),
+            None => "none".to_string(),
+        };
+        notes.push(format!(
+            "submodule {} -> {}",
+            short(&submodule.old_sha),
+            short(&submodule.new_sha)
         ));
     }
+    match file.kind {
+        FileKind::Symlink => notes.push("symlink".to_string()),
+        FileKind::Executable if file.mode_change.is_none() => notes.push("executable".to_string()),
+        _ => {}
+    }
+    if let Some(mode) = &file.mode_change {
+        notes.push(format!("mode {} -> {}", mode.old, mode.new));
+    }
+    if file.lfs_pointer {
+        notes.push("LFS pointer".to_string());
+    }
+    notes
+}
 
-    prompt_parts.push("Each message MUST follow this format: <type>: <description>".to_string());
-    prompt_parts.push(type_selection_guidance);
-    prompt_parts.push(format!(
-        "Available <type>s, their descriptions, and EXAMPLES of their use are:\n{}",
-        commit_types_formatted.trim_end()
-    ));
+/// Everything a prompt template can refer to, apart from the built-in
+/// guidance texts.
+#[derive(Debug, Clone)]
+pub struct PromptInput<'a> {
+    /// The preprocessed diff.
+    pub diff: &'a str,
+    pub changes_summary: &'a StagedChangesSummary,
+    pub num_suggestions: u32,
+    pub previous_message: Option<&'a str>,
+    pub branch: Option<&'a str>,
+    /// Contents of the project's instruction files.
+    pub guidelines: Option<&'a str>,
+}
 
-    let consistency_instruction = if num_suggestions > 1 {
-        format!(
-            "For the {} variations requested, determine the single most appropriate <type> that best describes the overall changes, \
-            then create {} different descriptions using that same type. The variations should differ in wording, emphasis, or perspective, \
-            but should all use the same commit type that represents the primary nature of the entire changeset.",
-            num_suggestions, num_suggestions
-        )
-    } else {
-        "Choose the <type> that best describes the overall changes".to_string()
-    };
-
-    prompt_parts.push(format!(
-        "{}. Use the provided examples and hierarchy guidance above to ensure correct type usage.\n\
-        The <description> should be concise, start with a verb in the imperative mood if possible, and be between {} and {} characters.",
-        consistency_instruction, MIN_COMMIT_DESCRIPTION_CHARS, MAX_COMMIT_DESCRIPTION_CHARS
-    ));
+#[derive(Serialize)]
+struct TemplateContext<'a> {
+    diff: &'a str,
+    binary_file_changes: &'a [String],
+    structure_changes: &'a [String],
+    files: &'a [FileChange],
+    file_table: String,
+    num_suggestions: u32,
+    previous_message: Option<&'a str>,
+    branch: Option<&'a str>,
+    project_guidelines: Option<&'a str>,
+    commit_types: String,
+    commit_type_list: Vec<CommitType<'static>>,
+    type_selection_guidance: String,
+    diff_reading_guide: String,
+    min_description_chars: usize,
+    max_description_chars: usize,
+}
 
-    prompt_parts
-        .push("Do not include any other explanatory text, just the commit message(s).".to_string());
+/// A rendered prompt, split into the fixed instructions and the request about
+/// the staged changes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Prompt {
+    /// Sent as the system instruction where the provider has one; empty when
+    /// the template does not start with a `system` block.
+    pub system: String,
+    pub request: String,
+}
 
-    if let Some(prev_msg) = previous_message {
-        let num_variations_str = if num_suggestions > 1 {
-            format!("{} variations of it", num_suggestions)
-        } else {
-            "it".to_string()
-        };
-        prompt_parts.push(format!(
-            "The previous commit message was: '{}'. Please generate a new, improved message (or {} if multiple are requested) based on the changes, \
-            considering why the previous one might have been suboptimal. Ensure the <type> is appropriate for the changes, \
-            guided by the hierarchy and examples provided above. If generating multiple variations, they should all use the same improved type.",
-            prev_msg, num_variations_str
-        ));
+impl Prompt {
+    /// The whole prompt as a single text, exactly as the template rendered it.
+    pub fn text(&self) -> String {
+        format!("{}{}", self.system, self.request)
+    }
+}
+
+impl From<&str> for Prompt {
+    fn from(request: &str) -> Self {
+        Prompt {
+            system: String::new(),
+            request: request.to_string(),
+        }
     }
+}
 
-    prompt_parts.push(diff_reading_guide);
+/// A minijinja template that renders the prompt sent to the model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptTemplate {
+    name: String,
+    source: String,
+}
 
-    prompt_parts.push("Diff:\n\n---".to_string());
-    prompt_parts.push(if diff_content.trim().is_empty() {
-        "No textual diff provided or detected.".to_string()
-    } else {
-        diff_content.to_string()
-    });
-    prompt_parts.push("---".to_string());
+fn template_environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_auto_escape_callback(|_| AutoEscape::None);
+    env
+}
+
+impl PromptTemplate {
+    pub fn builtin() -> Self {
+        PromptTemplate {
+            name: "built-in prompt".to_string(),
+            source: DEFAULT_TEMPLATE.to_string(),
+        }
+    }
+
+    /// Reads and compiles a template file so syntax errors surface before
+    /// anything is sent to a model.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read prompt template {:?}", path))?;
+        let template = PromptTemplate {
+            name: path.display().to_string(),
+            source,
+        };
+        template_environment()
+            .template_from_named_str(&template.name, &template.source)
+            .with_context(|| format!("Invalid prompt template {:?}", path))?;
+        Ok(template)
+    }
 
-    prompt_parts.push("Binary file changes:".to_string());
-    prompt_parts.push(binary_changes_summary_str);
-    prompt_parts.push("---".to_string());
+    /// The template configured by `prompt.template`, resolved against the
+    /// repository root when relative, or the built-in one.
+    pub fn from_config(config: &PromptConfig, repo_path: &Path) -> Result<Self> {
+        match &config.template {
+            Some(path) => PromptTemplate::from_file(&repo_path.join(path)),
+            None => Ok(PromptTemplate::builtin()),
+        }
+    }
 
-    prompt_parts.push("Folder structure changes:".to_string());
-    prompt_parts.push(folder_structure_changes_summary_str);
-    prompt_parts.push("---".to_string());
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-    prompt_parts.join("\n\n")
+    /// Renders the prompt. A `{% block system %}` at the very start of the
+    /// template becomes `Prompt::system`; everything after it is the request.
+    pub fn render(&self, input: &PromptInput) -> Result<Prompt> {
+        let mut commit_type_list = COMMIT_TYPES.to_vec();
+        sort_commit_types(&mut commit_type_list);
+        let context = TemplateContext {
+            diff: input.diff,
+            binary_file_changes: &input.changes_summary.binary_file_changes,
+            structure_changes: &input.changes_summary.structure_changes,
+            files: &input.changes_summary.files,
+            file_table: format_file_table(&input.changes_summary.files),
+            num_suggestions: input.num_suggestions,
+            previous_message: input.previous_message,
+            branch: input.branch,
+            project_guidelines: input.guidelines,
+            commit_types: format_commit_types_for_prompt().trim_end().to_string(),
+            commit_type_list,
+            type_selection_guidance: build_type_selection_guidance(),
+            diff_reading_guide: build_diff_reading_guide(),
+            min_description_chars: MIN_COMMIT_DESCRIPTION_CHARS,
+            max_description_chars: MAX_COMMIT_DESCRIPTION_CHARS,
+        };
+        let env = template_environment();
+        let template = env
+            .template_from_named_str(&self.name, &self.source)
+            .with_context(|| format!("Invalid prompt template {}", self.name))?;
+        let mut rendered = template
+            .render_captured(&context)
+            .with_context(|| format!("Failed to render prompt template {}", self.name))?;
+        let system = match rendered.with_state_mut(|state| state.render_block(SYSTEM_BLOCK)) {
+            Ok(system) => system,
+            Err(e) if e.kind() == ErrorKind::UnknownBlock => String::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to render prompt template {}", self.name));
+            }
+        };
+        let text = rendered.into_output();
+        Ok(match text.strip_prefix(&system) {
+            Some(request) if !system.is_empty() => Prompt {
+                request: request.to_string(),
+                system,
+            },
+            _ => Prompt {
+                system: String::new(),
+                request: text,
+            },
+        })
+    }
+}
+
+/// Appends the reasons every suggestion was rejected to the request of
+/// `original_prompt`.
+pub fn build_repair_prompt(original_prompt: &Prompt, rejections: &[Rejection]) -> Prompt {
+    let mut feedback = String::from(
+        "Your previous answer was rejected because none of the suggested commit messages passed validation:\n",
+    );
+    for rejection in rejections {
+        let issues: Vec<String> = rejection.issues.iter().map(|i| i.to_string()).collect();
+        feedback.push_str(&format!(
+            "- \"{}\": {}\n",
+            rejection.suggestion,
+            issues.join("; ")
+        ));
+    }
+    feedback.push_str(&format!(
+        "Fix these problems. Use only the <type>s listed above, start the <description> with a verb in the imperative mood, \
+        and keep it between {} and {} characters.",
+        MIN_COMMIT_DESCRIPTION_CHARS, MAX_COMMIT_DESCRIPTION_CHARS
+    ));
+    Prompt {
+        system: original_prompt.system.clone(),
+        request: format!("{}\n\n{}", original_prompt.request, feedback),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::validate::Issue;
 
     #[test]
-    fn test_build_prompt_basic() {
+    fn test_builtin_prompt_basic() -> Result<()> {
         let preprocessed_diff_example = "[ADDED_LINE]: new line";
         let summary = StagedChangesSummary {
             binary_file_changes: vec!["added binary file: image.png".to_string()],
             structure_changes: vec!["renamed: old_dir/file.txt to new_dir/file.txt".to_string()],
+            files: Vec::new(),
         };
-        let prompt = build_prompt(preprocessed_diff_example, &summary, 1, None);
+        let prompt = PromptTemplate::builtin()
+            .render(&PromptInput {
+                diff: preprocessed_diff_example,
+                changes_summary: &summary,
+                num_suggestions: 1,
+                previous_message: None,
+                branch: None,
+                guidelines: None,
+            })?
+            .text();
         assert!(prompt.contains("Generate 1 Git commit message."));
         assert!(prompt.contains("- feat: A new feature or significant functionality addition"));
         assert!(prompt.contains(&format!(
@@ -277,13 +465,23 @@ mod tests {
         assert!(
             prompt.contains("[ADDED_LINE]: Any line starting with the marker '[ADDED_LINE]: '")
         );
+        Ok(())
     }
 
     #[test]
-    fn test_build_prompt_multiple_suggestions() {
+    fn test_builtin_prompt_multiple_suggestions() -> Result<()> {
         let preprocessed_diff_example = "[REMOVED_LINE]: old\n[ADDED_LINE]: new";
         let summary = StagedChangesSummary::default();
-        let prompt = build_prompt(preprocessed_diff_example, &summary, 5, None);
+        let prompt = PromptTemplate::builtin()
+            .render(&PromptInput {
+                diff: preprocessed_diff_example,
+                changes_summary: &summary,
+                num_suggestions: 5,
+                previous_message: None,
+                branch: None,
+                guidelines: None,
+            })?
+            .text();
         assert!(prompt.contains("Your task is to generate 5 *alternative* Git commit messages."));
         assert!(prompt.contains("All 5 variations should use the SAME commit type"));
         assert!(prompt.contains("CRITICAL: Type Selection Hierarchy and Guidance"));
@@ -291,14 +489,24 @@ mod tests {
         assert!(
             prompt.contains("[ADDED_LINE]: Any line starting with the marker '[ADDED_LINE]: '")
         );
+        Ok(())
     }
 
     #[test]
-    fn test_build_prompt_with_amend_single_suggestion() {
+    fn test_builtin_prompt_with_amend_single_suggestion() -> Result<()> {
         let preprocessed_diff_example = "[ADDED_LINE]: new content";
         let summary = StagedChangesSummary::default();
         let prev_msg = "fix: did a thing wrong";
-        let prompt = build_prompt(preprocessed_diff_example, &summary, 1, Some(prev_msg));
+        let prompt = PromptTemplate::builtin()
+            .render(&PromptInput {
+                diff: preprocessed_diff_example,
+                changes_summary: &summary,
+                num_suggestions: 1,
+                previous_message: Some(prev_msg),
+                branch: None,
+                guidelines: None,
+            })?
+            .text();
         assert!(prompt.contains("Generate 1 Git commit message."));
         assert!(prompt.contains(&format!("The previous commit message was: '{}'.", prev_msg)));
         assert!(prompt.contains(preprocessed_diff_example));
@@ -306,19 +514,62 @@ mod tests {
         assert!(
             prompt.contains("[ADDED_LINE]: Any line starting with the marker '[ADDED_LINE]: '")
         );
+        Ok(())
     }
 
     #[test]
-    fn test_build_prompt_no_textual_diff() {
+    fn test_builtin_prompt_no_textual_diff() -> Result<()> {
         let diff = "";
         let summary = StagedChangesSummary {
             binary_file_changes: vec!["added binary file: data.zip".to_string()],
             structure_changes: vec![],
+            files: Vec::new(),
         };
-        let prompt = build_prompt(diff, &summary, 1, None);
+        let prompt = PromptTemplate::builtin()
+            .render(&PromptInput {
+                diff,
+                changes_summary: &summary,
+                num_suggestions: 1,
+                previous_message: None,
+                branch: None,
+                guidelines: None,
+            })?
+            .text();
         assert!(prompt.contains("Diff:\n\n---\n\nNo textual diff provided or detected.\n\n---"));
         assert!(prompt.contains("Binary file changes:\n\nadded binary file: data.zip"));
         assert!(prompt.contains("Understanding the 'Diff' Section (How to Read Code Changes):"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_repair_prompt_lists_rejections() {
+        let rejections = vec![Rejection {
+            suggestion: "wip: Stuff".to_string(),
+            issues: vec![
+                Issue::UnknownType("wip".to_string()),
+                Issue::DescriptionTooShort(5),
+            ],
+        }];
+        let original = Prompt {
+            system: "INSTRUCTIONS".to_string(),
+            request: "ORIGINAL PROMPT".to_string(),
+        };
+        let repair = build_repair_prompt(&original, &rejections);
+        assert_eq!(repair.system, "INSTRUCTIONS");
+        let prompt = repair.request;
+        assert!(prompt.starts_with("ORIGINAL PROMPT\n\n"));
+        assert!(prompt.contains(
+            "- \"wip: Stuff\": 'wip' is not one of the allowed types; description is 5 characters, minimum is 10"
+        ));
+        assert!(prompt.contains("between 10 and 72 characters."));
+    }
+
+    #[test]
+    fn test_is_known_commit_type() {
+        assert!(is_known_commit_type("feat"));
+        assert!(is_known_commit_type("readme"));
+        assert!(!is_known_commit_type("Feat"));
+        assert!(!is_known_commit_type("wip"));
     }
 
     #[test]
@@ -359,4 +610,208 @@ mod tests {
         ));
         assert!(guide.contains("Your primary focus for understanding the *actual modifications* should be on the lines marked with '[ADDED_LINE]: ' and '[REMOVED_LINE]: '."));
     }
+
+    #[test]
+    fn test_custom_template_variables() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let path = temp_dir.path().join("prompt.jinja");
+        fs::write(
+            &path,
+            "Branch: {{ branch }}\n\
+             {{ num_suggestions }} {{ binary_file_changes|length }} {{ previous_message or 'none' }}\n\
+             {{ diff }}\n\
+             {% for t in commit_type_list %}{{ t.name }} {% endfor %}",
+        )?;
+        let template = PromptTemplate::from_file(&path)?;
+        let summary = StagedChangesSummary {
+            binary_file_changes: vec!["added binary file: a.png".to_string()],
+            structure_changes: vec![],
+            files: Vec::new(),
+        };
+        let prompt = template.render(&PromptInput {
+            diff: "[ADDED_LINE]: x",
+            changes_summary: &summary,
+            num_suggestions: 3,
+            previous_message: None,
+            branch: Some("feature/login"),
+            guidelines: None,
+        })?;
+        assert!(prompt.system.is_empty());
+        let lines: Vec<&str> = prompt.request.lines().collect();
+        assert_eq!(lines[0], "Branch: feature/login");
+        assert_eq!(lines[1], "3 1 none");
+        assert_eq!(lines[2], "[ADDED_LINE]: x");
+        assert!(lines[3].starts_with("feat fix revert perf "));
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_block_is_split_from_the_request() -> Result<()> {
+        let summary = StagedChangesSummary::default();
+        let input = PromptInput {
+            diff: "[ADDED_LINE]: new content",
+            changes_summary: &summary,
+            num_suggestions: 1,
+            previous_message: Some("fix: did a thing wrong"),
+            branch: None,
+            guidelines: Some("Mention ticket numbers."),
+        };
+        let prompt = PromptTemplate::builtin().render(&input)?;
+        assert!(
+            prompt
+                .system
+                .starts_with("Analyze the following code changes")
+        );
+        assert!(prompt.system.contains("Available <type>s"));
+        assert!(prompt.system.contains("Mention ticket numbers."));
+        assert!(prompt.system.contains("Understanding the 'Diff' Section"));
+        assert!(!prompt.system.contains("new content"));
+        assert!(
+            prompt
+                .request
+                .starts_with("The previous commit message was: 'fix: did a thing wrong'.")
+        );
+        assert!(prompt.request.contains("[ADDED_LINE]: new content"));
+
+        let other_change = PromptInput {
+            diff: "[REMOVED_LINE]: old content",
+            previous_message: None,
+            ..input.clone()
+        };
+        assert_eq!(
+            PromptTemplate::builtin().render(&other_change)?.system,
+            prompt.system
+        );
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let path = temp_dir.path().join("prompt.jinja");
+        fs::write(
+            &path,
+            "{% block system %}Rules.\n{% endblock %}Diff: {{ diff }}",
+        )?;
+        let prompt = PromptTemplate::from_file(&path)?.render(&input)?;
+        assert_eq!(prompt.system, "Rules.\n");
+        assert_eq!(prompt.request, "Diff: [ADDED_LINE]: new content");
+        assert_eq!(prompt.text(), "Rules.\nDiff: [ADDED_LINE]: new content");
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_templates_are_rejected() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let broken = temp_dir.path().join("broken.jinja");
+        fs::write(&broken, "{% if diff %}unterminated")?;
+        assert!(PromptTemplate::from_file(&broken).is_err());
+
+        let typo = temp_dir.path().join("typo.jinja");
+        fs::write(&typo, "{{ dif }}")?;
+        let template = PromptTemplate::from_file(&typo)?;
+        let summary = StagedChangesSummary::default();
+        let error = template
+            .render(&PromptInput {
+                diff: "",
+                changes_summary: &summary,
+                num_suggestions: 1,
+                previous_message: None,
+                branch: None,
+                guidelines: None,
+            })
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("undefined"));
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_from_config_resolves_against_repo() -> Result<()> {
+        let temp_dir = tempfile::TempDir::new()?;
+        fs::create_dir_all(temp_dir.path().join(".ai-commit"))?;
+        fs::write(temp_dir.path().join(".ai-commit/prompt.jinja"), "custom")?;
+        let config = PromptConfig {
+            template: Some(".ai-commit/prompt.jinja".into()),
+        };
+        let template = PromptTemplate::from_config(&config, temp_dir.path())?;
+        assert!(template.name().ends_with("prompt.jinja"));
+        assert_eq!(
+            PromptTemplate::from_config(&PromptConfig::default(), temp_dir.path())?,
+            PromptTemplate::builtin()
+        );
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_file_table() {
+        use crate::git::{FileStatus, ModeChange, SubmoduleChange};
+        let file = |status, path: &str, kind, lines: Option<(u64, u64)>| FileChange {
+            status,
+            path: path.to_string(),
+            old_path: None,
+            kind,
+            added: lines.map(|(added, _)| added),
+            removed: lines.map(|(_, removed)| removed),
+            mode_change: None,
+            submodule: None,
+            lfs_pointer: false,
+        };
+        let files = vec![
+            file(
+                FileStatus::Modified,
+                "src/main.rs",
+                FileKind::Regular,
+                Some((12, 3)),
+            ),
+            FileChange {
+                old_path: Some("a.rs".to_string()),
+                ..file(FileStatus::Renamed, "b.rs", FileKind::Regular, Some((0, 0)))
+            },
+            FileChange {
+                mode_change: Some(ModeChange {
+                    old: "100644".to_string(),
+                    new: "100755".to_string(),
+                }),
+                ..file(
+                    FileStatus::Modified,
+                    "run.sh",
+                    FileKind::Executable,
+                    Some((0, 0)),
+                )
+            },
+            FileChange {
+                submodule: Some(SubmoduleChange {
+                    old_sha: Some("1234567890".to_string()),
+                    new_sha: Some("abcdef0123".to_string()),
+                }),
+                ..file(
+                    FileStatus::Modified,
+                    "vendor/lib",
+                    FileKind::Submodule,
+                    Some((1, 1)),
+                )
+            },
+            file(FileStatus::Added, "logo.png", FileKind::Regular, None),
+            FileChange {
+                lfs_pointer: true,
+                ..file(
+                    FileStatus::Added,
+                    "model.bin",
+                    FileKind::Regular,
+                    Some((3, 0)),
+                )
+            },
+        ];
+        assert_eq!(
+            format_file_table(&files),
+            "St  Path          Lines   Notes\n\
+             M   src/main.rs   +12 -3\n\
+             R   a.rs -> b.rs  +0 -0\n\
+             M   run.sh        +0 -0   mode 100644 -> 100755\n\
+             M   vendor/lib    -       submodule 1234567 -> abcdef0\n\
+             A   logo.png      binary\n\
+             A   model.bin     +3 -0   LFS pointer"
+        );
+        assert_eq!(format_file_table(&[]), "");
+    }
 }
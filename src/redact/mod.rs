@@ -0,0 +1,82 @@
+//! Scrubs secrets from text before it reaches the terminal. API keys are
+//! registered as they are resolved; every error message built from a
+//! response body, a URL or a transport error goes through `redact`.
+
+use std::sync::Mutex;
+
+const REDACTED: &str = "[REDACTED]";
+/// Shorter values are too likely to occur in ordinary text.
+const MIN_SECRET_LEN: usize = 8;
+
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Adds `secret` to the values `redact` removes.
+pub fn register(secret: &str) {
+    let secret = secret.trim();
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap_or_else(|e| e.into_inner());
+    if !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+/// Replaces every registered secret, and the value of any `key=` query
+/// parameter, with a placeholder.
+pub fn redact(text: &str) -> String {
+    let mut redacted = text.to_string();
+    for secret in SECRETS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        redacted = redacted.replace(secret.as_str(), REDACTED);
+    }
+    redact_key_params(&redacted)
+}
+
+/// Scrubs `?key=…` and `&key=…`, as in URLs echoed by proxies or older
+/// versions of the Gemini client.
+fn redact_key_params(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("key=") {
+        let (before, after) = rest.split_at(start + "key=".len());
+        redacted.push_str(before);
+        let in_query = before[..start].ends_with(['?', '&']);
+        let end = after
+            .find(|c: char| c == '&' || c == '#' || c == '"' || c == '\'' || c.is_whitespace())
+            .unwrap_or(after.len());
+        if in_query && end > 0 && !after[..end].starts_with(REDACTED) {
+            redacted.push_str(REDACTED);
+        } else {
+            redacted.push_str(&after[..end]);
+        }
+        rest = &after[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacts_registered_secrets() {
+        register("sk-test-redact-0123456789");
+        register("short");
+        assert_eq!(
+            redact("Unauthorized: Bearer sk-test-redact-0123456789 (short)"),
+            "Unauthorized: Bearer [REDACTED] (short)"
+        );
+    }
+
+    #[test]
+    fn test_redacts_key_query_parameters() {
+        assert_eq!(
+            redact(
+                "error sending request for url (https://host/models/m:generateContent?key=AIzaUnknown&alt=sse)"
+            ),
+            "error sending request for url (https://host/models/m:generateContent?key=[REDACTED]&alt=sse)"
+        );
+        assert_eq!(redact("monkey=banana, ?key="), "monkey=banana, ?key=");
+    }
+}
@@ -0,0 +1,361 @@
+//! Test support: an in-process mock of the Gemini and OpenAI HTTP APIs.
+
+use crate::ai::fixtures::Fixture;
+use crate::config::{Config, ModelConfig, ProviderConfig};
+use anyhow::{Context, Result, bail};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Environment variable the mock config reads its (fake) API key from.
+const MOCK_API_KEY_ENV_VAR: &str = "AI_COMMIT_MOCK_API_KEY";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Pause between the events of a streamed body.
+    pub event_delay: Duration,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: &str) -> Self {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: body.to_string(),
+            event_delay: Duration::ZERO,
+        }
+    }
+
+    /// A successful event stream carrying `events` as `data` payloads.
+    pub fn event_stream(events: &[serde_json::Value]) -> Self {
+        let body: String = events
+            .iter()
+            .map(|event| format!("data: {}\r\n\r\n", event))
+            .collect();
+        let mut response = MockResponse::new(200, &body);
+        response
+            .headers
+            .push(("Content-Type".to_string(), "text/event-stream".to_string()));
+        response
+    }
+
+    /// A streamed `streamGenerateContent?alt=sse` response. Each text is sent
+    /// one line at a time as its own candidate, which then finishes.
+    pub fn gemini_stream(texts: &[&str]) -> Self {
+        let mut events = Vec::new();
+        for (index, text) in texts.iter().enumerate() {
+            for piece in text.split_inclusive('\n') {
+                events.push(serde_json::json!({"candidates": [{
+                    "index": index,
+                    "content": {"role": "model", "parts": [{"text": piece}]}
+                }]}));
+            }
+            events.push(serde_json::json!({"candidates": [{
+                "index": index,
+                "content": {"role": "model", "parts": [{"text": ""}]},
+                "finishReason": "STOP"
+            }]}));
+        }
+        MockResponse::event_stream(&events)
+    }
+
+    /// A streamed chat completions response, one line of each text per chunk.
+    pub fn openai_stream(texts: &[&str]) -> Self {
+        let mut events = Vec::new();
+        for (index, text) in texts.iter().enumerate() {
+            for piece in text.split_inclusive('\n') {
+                events.push(serde_json::json!({"choices": [{
+                    "index": index,
+                    "delta": {"content": piece},
+                    "finish_reason": null
+                }]}));
+            }
+            events.push(serde_json::json!({"choices": [{
+                "index": index,
+                "delta": {},
+                "finish_reason": "stop"
+            }]}));
+        }
+        let mut response = MockResponse::event_stream(&events);
+        response.body.push_str("data: [DONE]\r\n\r\n");
+        response
+    }
+
+    pub fn with_event_delay(mut self, delay: Duration) -> Self {
+        self.event_delay = delay;
+        self
+    }
+
+    /// A successful `generateContent` response with one candidate per text.
+    pub fn gemini(texts: &[&str]) -> Self {
+        let candidates: Vec<serde_json::Value> = texts
+            .iter()
+            .map(|text| {
+                serde_json::json!({
+                    "content": {"role": "model", "parts": [{"text": text}]},
+                    "finishReason": "STOP"
+                })
+            })
+            .collect();
+        MockResponse::new(
+            200,
+            &serde_json::json!({ "candidates": candidates }).to_string(),
+        )
+    }
+
+    /// A successful chat completions response with one choice per text.
+    pub fn openai(texts: &[&str]) -> Self {
+        let choices: Vec<serde_json::Value> = texts
+            .iter()
+            .enumerate()
+            .map(|(index, text)| {
+                serde_json::json!({
+                    "index": index,
+                    "message": {"role": "assistant", "content": text},
+                    "finish_reason": "stop"
+                })
+            })
+            .collect();
+        MockResponse::new(200, &serde_json::json!({ "choices": choices }).to_string())
+    }
+
+    /// A Gemini-style 429 asking the client to retry immediately.
+    pub fn rate_limited() -> Self {
+        let mut response = MockResponse::new(
+            429,
+            r#"{"error": {"code": 429, "message": "Resource has been exhausted", "status": "RESOURCE_EXHAUSTED"}}"#,
+        );
+        response
+            .headers
+            .push(("Retry-After".to_string(), "0".to_string()));
+        response
+    }
+
+    pub fn malformed_json() -> Self {
+        MockResponse::new(200, r#"{"candidates": [{"content": "#)
+    }
+
+    pub fn from_fixture(fixture: &Fixture) -> Self {
+        let mut response = MockResponse::new(fixture.status, &fixture.body);
+        if let Some(retry_after) = &fixture.retry_after {
+            response
+                .headers
+                .push(("Retry-After".to_string(), retry_after.clone()));
+        }
+        if let Some(content_type) = &fixture.content_type {
+            response
+                .headers
+                .push(("Content-Type".to_string(), content_type.clone()));
+        }
+        response
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path including the query string.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    /// The value of the first header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serves canned responses in order, one per request, and records every
+/// request it receives. Once the script is exhausted it answers with 500.
+pub struct MockLlmServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockLlmServer {
+    pub fn start(responses: Vec<MockResponse>) -> Result<Self> {
+        let listener =
+            TcpListener::bind("127.0.0.1:0").context("Failed to bind mock LLM server")?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread_requests = Arc::clone(&requests);
+        let thread_shutdown = Arc::clone(&shutdown);
+        let handle = thread::spawn(move || {
+            let mut script = responses.into_iter();
+            for stream in listener.incoming() {
+                if thread_shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(mut stream) = stream else { continue };
+                let Ok(request) = read_request(&stream) else {
+                    continue;
+                };
+                // Record before answering so the client never observes a
+                // response whose request is missing from `requests()`.
+                thread_requests.lock().unwrap().push(request);
+                let response = script.next().unwrap_or_else(|| {
+                    MockResponse::new(500, "mock LLM server has no more scripted responses")
+                });
+                let _ = write_response(&mut stream, &response);
+            }
+        });
+
+        Ok(MockLlmServer {
+            addr,
+            requests,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    /// Replays fixture files written in record mode, in file name order.
+    pub fn from_fixtures(dir: &Path) -> Result<Self> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read fixture directory {:?}", dir))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+        if paths.is_empty() {
+            bail!("No fixtures found in {:?}", dir);
+        }
+        let mut responses = Vec::new();
+        for path in paths {
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read fixture {:?}", path))?;
+            let fixture: Fixture = serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse fixture {:?}", path))?;
+            responses.push(MockResponse::from_fixture(&fixture));
+        }
+        MockLlmServer::start(responses)
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// A config that sends every provider to this server, with caching and the
+    /// usage log off and retries fast enough for tests.
+    pub fn config(&self, models: &[&str]) -> Config {
+        // SAFETY: only this harness uses the variable, and always sets the same value.
+        unsafe {
+            env::set_var(MOCK_API_KEY_ENV_VAR, "mock-api-key");
+        }
+        let provider = ProviderConfig {
+            base_url: Some(self.base_url()),
+            api_key_env: Some(MOCK_API_KEY_ENV_VAR.to_string()),
+            ..ProviderConfig::default()
+        };
+        let mut config = Config {
+            models: models
+                .iter()
+                .map(|model| model.parse::<ModelConfig>().unwrap())
+                .collect(),
+            ..Config::default()
+        };
+        config.providers.gemini = provider.clone();
+        config.providers.openai = provider;
+        config.cache.enabled = false;
+        config.usage.log = false;
+        config.retry.initial_backoff_ms = 1;
+        config.retry.max_backoff_ms = 5;
+        config
+    }
+}
+
+impl Drop for MockLlmServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the shutdown flag.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Result<RecordedRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(RecordedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn write_response(stream: &mut TcpStream, response: &MockResponse) -> Result<()> {
+    let mut raw = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    if !response
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+    {
+        raw.push_str("Content-Type: application/json\r\n");
+    }
+    for (name, value) in &response.headers {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw.push_str("\r\n");
+    stream.write_all(raw.as_bytes())?;
+    if response.event_delay.is_zero() {
+        stream.write_all(response.body.as_bytes())?;
+    } else {
+        for event in response.body.split_inclusive("\r\n\r\n") {
+            stream.write_all(event.as_bytes())?;
+            stream.flush()?;
+            thread::sleep(response.event_delay);
+        }
+    }
+    stream.flush()?;
+    Ok(())
+}
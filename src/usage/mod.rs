@@ -0,0 +1,384 @@
+//! Token accounting: cost estimates from the `[pricing]` table and a JSONL log
+//! with one record per generation, summarized by `ai-commit usage`.
+
+use crate::ai::Usage;
+use crate::config::{Config, ModelConfig, UsageConfig};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One line of the usage log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Work tree root the generation ran in.
+    pub repo: String,
+    /// `provider/model`.
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// `None` when the model has no `[pricing]` entry.
+    pub cost_usd: Option<f64>,
+}
+
+impl UsageRecord {
+    pub fn new(repo_path: &Path, model: &ModelConfig, usage: Usage, config: &Config) -> Self {
+        UsageRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            repo: repo_path.display().to_string(),
+            model: model.to_string(),
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cost_usd: estimate_cost(config, model, usage),
+        }
+    }
+}
+
+/// The cost of `usage` in US dollars, if the model is in the price table.
+pub fn estimate_cost(config: &Config, model: &ModelConfig, usage: Usage) -> Option<f64> {
+    let price = config.pricing.get(&model.to_string())?;
+    Some(
+        (usage.input_tokens as f64 * price.input_per_million
+            + usage.output_tokens as f64 * price.output_per_million)
+            / 1_000_000.0,
+    )
+}
+
+/// One line for the terminal, e.g. `1,204 input + 38 output tokens, ~$0.0002`.
+pub fn describe(usage: Usage, cost_usd: Option<f64>) -> String {
+    let tokens = format!(
+        "{} input + {} output tokens",
+        thousands(usage.input_tokens),
+        thousands(usage.output_tokens)
+    );
+    match cost_usd {
+        Some(cost) => format!("{}, ~{}", tokens, dollars(cost)),
+        None => tokens,
+    }
+}
+
+pub fn thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+fn dollars(amount: f64) -> String {
+    if amount != 0.0 && amount < 0.01 {
+        format!("${:.4}", amount)
+    } else {
+        format!("${:.2}", amount)
+    }
+}
+
+/// `[usage] path`, or `ai-commit/usage.jsonl` in the platform data directory.
+pub fn log_path(config: &UsageConfig) -> Option<PathBuf> {
+    match &config.path {
+        Some(path) => Some(path.clone()),
+        None => Some(dirs::data_dir()?.join("ai-commit").join("usage.jsonl")),
+    }
+}
+
+pub fn append(path: &Path, record: &UsageRecord) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    let mut line = serde_json::to_string(record).context("Failed to serialize usage record")?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("Failed to write usage log {:?}", path))
+}
+
+/// Reads the log, skipping lines that do not parse (e.g. one cut short by a
+/// crash). A missing log is empty.
+pub fn read_log(path: &Path) -> Result<Vec<UsageRecord>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read usage log {:?}", path))?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Day,
+    Repo,
+    Model,
+}
+
+impl GroupBy {
+    pub const ALL: [GroupBy; 3] = [GroupBy::Day, GroupBy::Repo, GroupBy::Model];
+
+    fn name(self) -> &'static str {
+        match self {
+            GroupBy::Day => "day",
+            GroupBy::Repo => "repo",
+            GroupBy::Model => "model",
+        }
+    }
+
+    fn key(self, record: &UsageRecord) -> String {
+        match self {
+            GroupBy::Day => utc_date(record.timestamp),
+            GroupBy::Repo => record.repo.clone(),
+            GroupBy::Model => record.model.clone(),
+        }
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "day" => Ok(GroupBy::Day),
+            "repo" => Ok(GroupBy::Repo),
+            "model" => Ok(GroupBy::Model),
+            _ => bail!("Unknown grouping '{}'. Expected day, repo or model.", s),
+        }
+    }
+}
+
+/// Totals for one group of records.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Totals {
+    pub runs: usize,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: f64,
+    /// Runs of models without a price, whose cost is missing from `cost_usd`.
+    pub unpriced_runs: usize,
+}
+
+impl Totals {
+    fn add(&mut self, record: &UsageRecord) {
+        self.runs += 1;
+        self.input_tokens += record.input_tokens;
+        self.output_tokens += record.output_tokens;
+        match record.cost_usd {
+            Some(cost) => self.cost_usd += cost,
+            None => self.unpriced_runs += 1,
+        }
+    }
+}
+
+pub fn summarize(records: &[UsageRecord], group_by: GroupBy) -> BTreeMap<String, Totals> {
+    let mut groups: BTreeMap<String, Totals> = BTreeMap::new();
+    for record in records {
+        groups.entry(group_by.key(record)).or_default().add(record);
+    }
+    groups
+}
+
+/// A table per grouping, followed by the overall total.
+pub fn format_summary(records: &[UsageRecord], groupings: &[GroupBy]) -> String {
+    let tables: Vec<(String, BTreeMap<String, Totals>)> = groupings
+        .iter()
+        .map(|&group_by| {
+            (
+                format!("By {}", group_by.name()),
+                summarize(records, group_by),
+            )
+        })
+        .collect();
+    let width = tables
+        .iter()
+        .flat_map(|(heading, groups)| std::iter::once(heading).chain(groups.keys()))
+        .map(|label| label.len())
+        .fold("Total".len(), usize::max);
+
+    let mut out = String::new();
+    for (heading, groups) in &tables {
+        let _ = writeln!(
+            out,
+            "{:width$}  {:>5}  {:>12}  {:>12}  {:>10}",
+            heading,
+            "runs",
+            "input",
+            "output",
+            "cost",
+            width = width
+        );
+        for (key, totals) in groups {
+            let _ = writeln!(out, "{}", format_row(key, totals, width));
+        }
+        out.push('\n');
+    }
+    let mut total = Totals::default();
+    for record in records {
+        total.add(record);
+    }
+    out.push_str(&format_row("Total", &total, width));
+    if total.unpriced_runs > 0 {
+        let _ = write!(
+            out,
+            "\n{} run(s) used models without a [pricing] entry and are not in the cost.",
+            total.unpriced_runs
+        );
+    }
+    out
+}
+
+fn format_row(key: &str, totals: &Totals, width: usize) -> String {
+    let cost = if totals.unpriced_runs == totals.runs {
+        "—".to_string()
+    } else {
+        dollars(totals.cost_usd)
+    };
+    format!(
+        "{:width$}  {:>5}  {:>12}  {:>12}  {:>10}",
+        key,
+        totals.runs,
+        thousands(totals.input_tokens),
+        thousands(totals.output_tokens),
+        cost,
+        width = width
+    )
+}
+
+/// `YYYY-MM-DD` in UTC for a Unix timestamp.
+fn utc_date(timestamp: u64) -> String {
+    // Howard Hinnant's days-to-civil algorithm.
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModelPrice;
+    use tempfile::TempDir;
+
+    fn record(timestamp: u64, repo: &str, model: &str, cost_usd: Option<f64>) -> UsageRecord {
+        UsageRecord {
+            timestamp,
+            repo: repo.to_string(),
+            model: model.to_string(),
+            input_tokens: 1_000,
+            output_tokens: 50,
+            cost_usd,
+        }
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let mut config = Config::default();
+        let model: ModelConfig = "gemini/gemini-2.5-flash".parse().unwrap();
+        let usage = Usage {
+            input_tokens: 2_000_000,
+            output_tokens: 100_000,
+        };
+        assert_eq!(estimate_cost(&config, &model, usage), None);
+        config.pricing.insert(
+            "gemini/gemini-2.5-flash".to_string(),
+            ModelPrice {
+                input_per_million: 0.5,
+                output_per_million: 2.0,
+            },
+        );
+        assert_eq!(estimate_cost(&config, &model, usage), Some(1.2));
+        assert_eq!(
+            describe(usage, Some(1.2)),
+            "2,000,000 input + 100,000 output tokens, ~$1.20"
+        );
+        assert_eq!(
+            describe(Usage::default(), Some(0.00012)),
+            "0 input + 0 output tokens, ~$0.0001"
+        );
+    }
+
+    #[test]
+    fn test_utc_date() {
+        assert_eq!(utc_date(0), "1970-01-01");
+        assert_eq!(utc_date(951_782_400), "2000-02-29");
+        assert_eq!(utc_date(1_792_281_599), "2026-10-17");
+        assert_eq!(utc_date(1_792_281_600), "2026-10-18");
+    }
+
+    #[test]
+    fn test_log_round_trip_skips_damaged_lines() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("data/usage.jsonl");
+        let first = record(1_000, "/src/app", "gemini/flash", Some(0.001));
+        append(&path, &first)?;
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"{\"timestamp\": 12\n")?;
+        let second = record(2_000, "/src/app", "openai/gpt-4o-mini", None);
+        append(&path, &second)?;
+        assert_eq!(read_log(&path)?, vec![first, second]);
+        assert!(read_log(&temp_dir.path().join("missing.jsonl"))?.is_empty());
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_summarize_by_day_repo_and_model() {
+        let records = vec![
+            record(86_400, "/src/app", "gemini/flash", Some(0.25)),
+            record(86_400 + 60, "/src/lib", "gemini/flash", Some(0.5)),
+            record(2 * 86_400, "/src/app", "openai/gpt-4o-mini", None),
+        ];
+        let by_day = summarize(&records, GroupBy::Day);
+        assert_eq!(
+            by_day.keys().collect::<Vec<_>>(),
+            ["1970-01-02", "1970-01-03"]
+        );
+        assert_eq!(by_day["1970-01-02"].runs, 2);
+        assert_eq!(by_day["1970-01-02"].cost_usd, 0.75);
+        let by_repo = summarize(&records, GroupBy::Repo);
+        assert_eq!(by_repo["/src/app"].input_tokens, 2_000);
+        assert_eq!(by_repo["/src/app"].unpriced_runs, 1);
+        let by_model = summarize(&records, GroupBy::Model);
+        assert_eq!(by_model["gemini/flash"].output_tokens, 100);
+
+        let report = format_summary(&records, &[GroupBy::Model]);
+        assert!(report.starts_with("By model"), "{}", report);
+        assert!(report.contains("Total"), "{}", report);
+        assert!(report.contains("$0.75"), "{}", report);
+        assert!(
+            report.ends_with(
+                "1 run(s) used models without a [pricing] entry and are not in the cost."
+            )
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use crate::prompt::{
+    MAX_COMMIT_DESCRIPTION_CHARS, MIN_COMMIT_DESCRIPTION_CHARS, is_known_commit_type,
+};
+use std::fmt;
+
+// Common ways models misspell a type from the taxonomy.
+const COMMIT_TYPE_ALIASES: &[(&str, &str)] = &[
+    ("feature", "feat"),
+    ("features", "feat"),
+    ("bugfix", "fix"),
+    ("hotfix", "fix"),
+    ("fixes", "fix"),
+    ("doc", "docs"),
+    ("documentation", "docs"),
+    ("tests", "test"),
+    ("testing", "test"),
+    ("refactoring", "refactor"),
+    ("performance", "perf"),
+    ("styles", "style"),
+    ("chores", "chore"),
+];
+
+// Base forms of verbs that commonly open a commit description. Inflected
+// forms of these ("Added", "Adds", "Adding") are rewritten to the base form.
+const IMPERATIVE_VERBS: &[&str] = &[
+    "add",
+    "adjust",
+    "allow",
+    "apply",
+    "avoid",
+    "bump",
+    "cache",
+    "change",
+    "clean",
+    "configure",
+    "convert",
+    "correct",
+    "create",
+    "delete",
+    "deprecate",
+    "disable",
+    "document",
+    "drop",
+    "enable",
+    "ensure",
+    "expose",
+    "extract",
+    "fix",
+    "format",
+    "generate",
+    "handle",
+    "implement",
+    "improve",
+    "include",
+    "increase",
+    "initialize",
+    "install",
+    "integrate",
+    "introduce",
+    "limit",
+    "load",
+    "log",
+    "merge",
+    "migrate",
+    "move",
+    "normalize",
+    "optimize",
+    "parse",
+    "pass",
+    "polish",
+    "prevent",
+    "reduce",
+    "refactor",
+    "release",
+    "remove",
+    "rename",
+    "reorganize",
+    "replace",
+    "resolve",
+    "restore",
+    "restructure",
+    "return",
+    "revert",
+    "rewrite",
+    "show",
+    "simplify",
+    "skip",
+    "sort",
+    "split",
+    "store",
+    "strip",
+    "support",
+    "switch",
+    "test",
+    "tweak",
+    "update",
+    "upgrade",
+    "use",
+    "validate",
+    "wrap",
+];
+
+// Words ending in "ed"/"ing" that are not past tense or gerunds.
+const IMPERATIVE_EXCEPTIONS: &[&str] = &[
+    "bring",
+    "embed",
+    "everything",
+    "feed",
+    "nothing",
+    "seed",
+    "shed",
+    "something",
+    "speed",
+    "string",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConventionalCommit {
+    pub commit_type: String,
+    pub scope: Option<String>,
+    pub breaking: bool,
+    pub description: String,
+}
+
+impl ConventionalCommit {
+    pub fn parse(message: &str) -> Option<Self> {
+        let (header, description) = message.split_once(':')?;
+        let header = header.trim();
+        let (header, breaking) = match header.strip_suffix('!') {
+            Some(stripped) => (stripped, true),
+            None => (header, false),
+        };
+
+        let (commit_type, scope) = match header.split_once('(') {
+            Some((commit_type, rest)) => {
+                let scope = rest.strip_suffix(')')?.trim();
+                let scope = (!scope.is_empty()).then(|| scope.to_string());
+                (commit_type.trim(), scope)
+            }
+            None => (header, None),
+        };
+
+        if commit_type.is_empty()
+            || !commit_type
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return None;
+        }
+
+        Some(ConventionalCommit {
+            commit_type: commit_type.to_string(),
+            scope,
+            breaking,
+            description: description.trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ConventionalCommit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.commit_type)?;
+        if let Some(scope) = &self.scope {
+            write!(f, "({})", scope)?;
+        }
+        if self.breaking {
+            write!(f, "!")?;
+        }
+        write!(f, ": {}", self.description)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    Malformed,
+    UnknownType(String),
+    DescriptionTooShort(usize),
+    DescriptionTooLong(usize),
+    NotImperative(String),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Malformed => write!(f, "does not follow the '<type>: <description>' format"),
+            Issue::UnknownType(t) => write!(f, "'{}' is not one of the allowed types", t),
+            Issue::DescriptionTooShort(len) => write!(
+                f,
+                "description is {} characters, minimum is {}",
+                len, MIN_COMMIT_DESCRIPTION_CHARS
+            ),
+            Issue::DescriptionTooLong(len) => write!(
+                f,
+                "description is {} characters, maximum is {}",
+                len, MAX_COMMIT_DESCRIPTION_CHARS
+            ),
+            Issue::NotImperative(word) => write!(
+                f,
+                "description starts with '{}' instead of a verb in the imperative mood",
+                word
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub suggestion: String,
+    pub issues: Vec<Issue>,
+}
+
+fn capitalize_first(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn inflections(base: &str) -> Vec<String> {
+    let mut forms = Vec::new();
+    if let Some(stem) = base.strip_suffix('y') {
+        forms.push(format!("{}ies", stem));
+        forms.push(format!("{}ied", stem));
+        forms.push(format!("{}ying", stem));
+        return forms;
+    }
+    if base.ends_with('s') || base.ends_with("sh") || base.ends_with("ch") || base.ends_with('x') {
+        forms.push(format!("{}es", base));
+    } else {
+        forms.push(format!("{}s", base));
+    }
+    if let Some(stem) = base.strip_suffix('e') {
+        forms.push(format!("{}ed", stem));
+        forms.push(format!("{}d", base));
+        forms.push(format!("{}ing", stem));
+        return forms;
+    }
+    forms.push(format!("{}ed", base));
+    forms.push(format!("{}ing", base));
+    // Short consonant-vowel-consonant verbs double their last letter.
+    let chars: Vec<char> = base.chars().collect();
+    if let [.., a, b, c] = chars[..] {
+        let is_vowel = |ch: char| "aeiou".contains(ch);
+        if !is_vowel(a) && is_vowel(b) && !is_vowel(c) && !"wxy".contains(c) {
+            forms.push(format!("{}{}ed", base, c));
+            forms.push(format!("{}{}ing", base, c));
+        }
+    }
+    forms
+}
+
+fn imperative_form(word: &str) -> Option<&'static str> {
+    let lower = word.to_lowercase();
+    IMPERATIVE_VERBS
+        .iter()
+        .find(|base| inflections(base).contains(&lower))
+        .copied()
+}
+
+fn looks_non_imperative(word: &str) -> bool {
+    let lower = word.to_lowercase();
+    if IMPERATIVE_VERBS.contains(&lower.as_str()) || IMPERATIVE_EXCEPTIONS.contains(&lower.as_str())
+    {
+        return false;
+    }
+    lower.len() > 4 && (lower.ends_with("ed") || lower.ends_with("ing"))
+}
+
+fn repair_description(description: &str) -> Result<String, Issue> {
+    let mut description = description
+        .trim()
+        .trim_end_matches('.')
+        .trim_end()
+        .to_string();
+
+    let first_word_len = description
+        .find(char::is_whitespace)
+        .unwrap_or(description.len());
+    let first_word = &description[..first_word_len];
+    if let Some(base) = imperative_form(first_word) {
+        description = format!("{}{}", base, &description[first_word_len..]);
+    } else if looks_non_imperative(first_word) {
+        return Err(Issue::NotImperative(first_word.to_string()));
+    }
+
+    Ok(capitalize_first(&description))
+}
+
+/// Checks a suggestion against the commit conventions in the prompt, repairing
+/// what can be fixed mechanically (type case and aliases, trailing period,
+/// capitalization, inflected leading verbs).
+pub fn validate_suggestion(suggestion: &str) -> Result<String, Vec<Issue>> {
+    let Some(mut commit) = ConventionalCommit::parse(suggestion) else {
+        return Err(vec![Issue::Malformed]);
+    };
+    let mut issues = Vec::new();
+
+    let lower_type = commit.commit_type.to_lowercase();
+    let resolved_type = COMMIT_TYPE_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == lower_type)
+        .map(|(_, canonical)| canonical.to_string())
+        .unwrap_or(lower_type);
+    if is_known_commit_type(&resolved_type) {
+        commit.commit_type = resolved_type;
+    } else {
+        issues.push(Issue::UnknownType(commit.commit_type.clone()));
+    }
+
+    match repair_description(&commit.description) {
+        Ok(description) => commit.description = description,
+        Err(issue) => issues.push(issue),
+    }
+
+    let description_len = commit.description.chars().count();
+    if description_len < MIN_COMMIT_DESCRIPTION_CHARS {
+        issues.push(Issue::DescriptionTooShort(description_len));
+    } else if description_len > MAX_COMMIT_DESCRIPTION_CHARS {
+        issues.push(Issue::DescriptionTooLong(description_len));
+    }
+
+    if issues.is_empty() {
+        Ok(commit.to_string())
+    } else {
+        Err(issues)
+    }
+}
+
+/// Splits suggestions into repaired, de-duplicated valid messages and the
+/// rejected ones along with the reasons they failed.
+pub fn validate_suggestions(suggestions: Vec<String>) -> (Vec<String>, Vec<Rejection>) {
+    let mut accepted: Vec<String> = Vec::new();
+    let mut rejected = Vec::new();
+    for suggestion in suggestions {
+        match validate_suggestion(&suggestion) {
+            Ok(message) => {
+                if !accepted.contains(&message) {
+                    accepted.push(message);
+                }
+            }
+            Err(issues) => rejected.push(Rejection { suggestion, issues }),
+        }
+    }
+    (accepted, rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_type_scope_and_breaking() {
+        let commit = ConventionalCommit::parse("feat(api)!: Drop legacy endpoints").unwrap();
+        assert_eq!(commit.commit_type, "feat");
+        assert_eq!(commit.scope.as_deref(), Some("api"));
+        assert!(commit.breaking);
+        assert_eq!(commit.description, "Drop legacy endpoints");
+        assert_eq!(commit.to_string(), "feat(api)!: Drop legacy endpoints");
+
+        let no_scope = ConventionalCommit::parse("fix: Handle empty input").unwrap();
+        assert_eq!(no_scope.scope, None);
+        assert!(!no_scope.breaking);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_headers() {
+        assert!(ConventionalCommit::parse("no colon here").is_none());
+        assert!(ConventionalCommit::parse(": Missing type").is_none());
+        assert!(ConventionalCommit::parse("feat(api: Unclosed scope").is_none());
+        assert!(ConventionalCommit::parse("some words: Not a type").is_none());
+    }
+
+    #[test]
+    fn test_validate_accepts_clean_suggestion() {
+        assert_eq!(
+            validate_suggestion("feat: Implement user authentication via OAuth"),
+            Ok("feat: Implement user authentication via OAuth".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_repairs_fixable_issues() {
+        assert_eq!(
+            validate_suggestion("Feat: add pagination to the user list."),
+            Ok("feat: Add pagination to the user list".to_string())
+        );
+        assert_eq!(
+            validate_suggestion("bugfix(parser): Fixed crash on empty input"),
+            Ok("fix(parser): Fix crash on empty input".to_string())
+        );
+        assert_eq!(
+            validate_suggestion("refactor: Simplifies the retry logic in client"),
+            Ok("refactor: Simplify the retry logic in client".to_string())
+        );
+        assert_eq!(
+            validate_suggestion("chore: Dropping unused build scripts"),
+            Ok("chore: Drop unused build scripts".to_string())
+        );
+        assert_eq!(
+            validate_suggestion("docs: Updating README with setup steps"),
+            Ok("docs: Update README with setup steps".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_unfixable_issues() {
+        assert_eq!(
+            validate_suggestion("wip: Implement the new parser"),
+            Err(vec![Issue::UnknownType("wip".to_string())])
+        );
+        assert_eq!(
+            validate_suggestion("fix: Typo"),
+            Err(vec![Issue::DescriptionTooShort(4)])
+        );
+        let long_description = "a".repeat(MAX_COMMIT_DESCRIPTION_CHARS + 1);
+        assert_eq!(
+            validate_suggestion(&format!("feat: Add {}", long_description)),
+            Err(vec![Issue::DescriptionTooLong(
+                MAX_COMMIT_DESCRIPTION_CHARS + 5
+            )])
+        );
+        assert_eq!(
+            validate_suggestion("feat: Reworked the onboarding flow"),
+            Err(vec![Issue::NotImperative("Reworked".to_string())])
+        );
+        assert_eq!(
+            validate_suggestion("not a commit message"),
+            Err(vec![Issue::Malformed])
+        );
+    }
+
+    #[test]
+    fn test_imperative_exceptions_are_kept() {
+        assert_eq!(
+            validate_suggestion("perf: Speed up diff preprocessing"),
+            Ok("perf: Speed up diff preprocessing".to_string())
+        );
+        assert_eq!(
+            validate_suggestion("feat: Embed version info in the binary"),
+            Ok("feat: Embed version info in the binary".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_suggestions_splits_and_dedupes() {
+        let (accepted, rejected) = validate_suggestions(vec![
+            "feat: Add retry support to the client".to_string(),
+            "feat: Added retry support to the client.".to_string(),
+            "oops: Something unexpected happened".to_string(),
+        ]);
+        assert_eq!(accepted, vec!["feat: Add retry support to the client"]);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(
+            rejected[0].suggestion,
+            "oops: Something unexpected happened"
+        );
+        assert_eq!(
+            rejected[0].issues,
+            vec![Issue::UnknownType("oops".to_string())]
+        );
+    }
+}